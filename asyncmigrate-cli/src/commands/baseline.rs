@@ -0,0 +1,48 @@
+use super::Command;
+use crate::utils::ResultExt;
+use async_trait::async_trait;
+use asyncmigrate::{Migration, MigrationChangeSets, MigrationError};
+use clap::{App, Arg, ArgMatches};
+
+pub struct BaselineCommand;
+
+#[async_trait]
+impl Command for BaselineCommand {
+    fn command_name(&self) -> &'static str {
+        "baseline"
+    }
+    fn config_subcommand(&self, app: App<'static, 'static>) -> App<'static, 'static> {
+        crate::utils::common_args(app.about("Record change sets as applied without running them"))
+            .arg(
+                Arg::with_name("group_name")
+                    .index(1)
+                    .help("Target group name")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("version")
+                    .index(2)
+                    .help("Last version which already exists in the database")
+                    .takes_value(true)
+                    .required(true),
+            )
+    }
+    async fn run(&self, matches: &ArgMatches<'static>) -> Result<(), MigrationError> {
+        let config = crate::utils::load_config(matches).context("Failed to load config")?;
+        let mut connect = crate::utils::connect(&config).await?;
+
+        let group_name = matches.value_of("group_name").unwrap();
+        let version = matches.value_of("version").unwrap().parse()?;
+        let one_change_sets = config
+            .changesets
+            .iter()
+            .find(|x| x.group_name == group_name)
+            .ok_or_else(|| MigrationError::UnknownGroupError(group_name.to_string()))?;
+        let local_changesets =
+            MigrationChangeSets::load_dir(&one_change_sets.group_name, &one_change_sets.directory)?;
+
+        connect.baseline(&local_changesets, version).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use super::Command;
+use async_trait::async_trait;
+use asyncmigrate::{Migration, MigrationError};
+use clap::{App, Arg, ArgMatches};
+
+pub struct HistoryCommand;
+
+#[async_trait]
+impl Command for HistoryCommand {
+    fn command_name(&self) -> &'static str {
+        "history"
+    }
+    fn config_subcommand(&self, app: App<'static, 'static>) -> App<'static, 'static> {
+        crate::utils::common_args(app.about("Show migration log, newest first"))
+            .arg(
+                Arg::with_name("group_name")
+                    .index(1)
+                    .help("Target group name")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("limit")
+                    .short("n")
+                    .long("limit")
+                    .help("# of entries per page")
+                    .takes_value(true)
+                    .default_value("20"),
+            )
+            .arg(
+                Arg::with_name("page")
+                    .short("p")
+                    .long("page")
+                    .help("Page number starting from 1")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
+    }
+    async fn run(&self, matches: &ArgMatches<'static>) -> Result<(), MigrationError> {
+        let config = crate::utils::load_config(matches)?;
+        let mut connect = crate::utils::connect(&config).await?;
+
+        let limit: usize = matches.value_of("limit").unwrap().parse()?;
+        let page: usize = matches.value_of("page").unwrap().parse()?;
+        let offset = page.saturating_sub(1).checked_mul(limit).ok_or_else(|| {
+            MigrationError::InvalidValueError {
+                kind: "page",
+                value: format!("{} with limit {}", page, limit),
+            }
+        })?;
+        let entries = connect
+            .load_log(matches.value_of("group_name"), offset, limit)
+            .await?;
+
+        for one in entries.iter() {
+            println!(
+                "{:>6} {} {:<20} {} V{} {} {} by {}@{} ({})",
+                one.id,
+                crate::utils::format_time(one.logged_at),
+                one.operation,
+                one.group_name,
+                one.version.map(|x| x.to_string()).unwrap_or_default(),
+                one.name.as_deref().unwrap_or(""),
+                if one.success { "OK" } else { "FAILED" },
+                one.actor,
+                one.hostname.as_deref().unwrap_or("-"),
+                one.tool_version.as_deref().unwrap_or("-"),
+            );
+            if let Some(error) = one.error.as_ref() {
+                println!("       {}", error);
+            }
+        }
+        Ok(())
+    }
+}
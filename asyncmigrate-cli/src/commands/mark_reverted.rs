@@ -0,0 +1,44 @@
+use super::Command;
+use async_trait::async_trait;
+use asyncmigrate::{Migration, MigrationError};
+use clap::{App, Arg, ArgMatches};
+
+pub struct MarkRevertedCommand;
+
+#[async_trait]
+impl Command for MarkRevertedCommand {
+    fn command_name(&self) -> &'static str {
+        "mark-reverted"
+    }
+    fn config_subcommand(&self, app: App<'static, 'static>) -> App<'static, 'static> {
+        crate::utils::common_args(
+            app.about("Remove a change set reverted by hand from history without running it"),
+        )
+        .arg(
+            Arg::with_name("group_name")
+                .index(1)
+                .help("Target group name")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("version")
+                .index(2)
+                .help("Version of the change set")
+                .takes_value(true)
+                .required(true),
+        )
+    }
+    async fn run(&self, matches: &ArgMatches<'static>) -> Result<(), MigrationError> {
+        let config = crate::utils::load_config(matches)?;
+        let mut connect = crate::utils::connect(&config).await?;
+
+        connect
+            .mark_reverted(
+                matches.value_of("group_name").unwrap(),
+                matches.value_of("version").unwrap().parse()?,
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -1,8 +1,12 @@
 use super::Command;
+use crate::utils::ResultExt;
 use async_trait::async_trait;
-use asyncmigrate::{Migration, MigrationChangeSets, MigrationError, MigrationErrorKind};
+use asyncmigrate::{
+    FanOutMigrator, Hooks, Migration, MigrationChangeSets, MigrationError, TargetStatus,
+    TenantMigrator, TenantSchemas,
+};
 use clap::{App, Arg, ArgMatches};
-use failure::ResultExt;
+use std::fs;
 
 pub struct MigrateCommand;
 
@@ -25,10 +29,87 @@ impl Command for MigrateCommand {
                     .help("# of change sets to apply")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("atomic")
+                    .long("atomic")
+                    .help("Apply all change sets of a group in one transaction"),
+            )
+            .arg(
+                Arg::with_name("tenant")
+                    .long("tenant")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Tenant schema to migrate, with history in the schema"),
+            )
+            .arg(
+                Arg::with_name("tenant-query")
+                    .long("tenant-query")
+                    .takes_value(true)
+                    .conflicts_with_all(&["tenant", "tenants-file"])
+                    .help("SQL query returning tenant schemas in the first column"),
+            )
+            .arg(
+                Arg::with_name("tenants-file")
+                    .long("tenants-file")
+                    .takes_value(true)
+                    .conflicts_with("tenant")
+                    .help("File listing tenant schemas, one per line"),
+            )
+            .arg(
+                Arg::with_name("parallel")
+                    .long("parallel")
+                    .takes_value(true)
+                    .help("# of tenant schemas migrated at once"),
+            )
+            .arg(
+                Arg::with_name("failed-file")
+                    .long("failed-file")
+                    .takes_value(true)
+                    .help("Write failed tenant schemas to the file to resume with --tenants-file"),
+            )
+            .arg(
+                Arg::with_name("target")
+                    .long("target")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .conflicts_with_all(&["url", "tenant", "tenant-query", "tenants-file"])
+                    .help("Database URL to migrate; repeat for more databases"),
+            )
+            .arg(
+                Arg::with_name("targets-command")
+                    .long("targets-command")
+                    .takes_value(true)
+                    .conflicts_with_all(&[
+                        "url",
+                        "target",
+                        "tenant",
+                        "tenant-query",
+                        "tenants-file",
+                    ])
+                    .help("Shell command printing database URLs to migrate, one per line"),
+            )
+            .arg(
+                Arg::with_name("concurrency")
+                    .long("concurrency")
+                    .takes_value(true)
+                    .help("# of target databases migrated at once"),
+            )
+            .arg(
+                Arg::with_name("fail-fast")
+                    .long("fail-fast")
+                    .help("Stop starting target databases after one of them failed"),
+            )
     }
     async fn run(&self, matches: &ArgMatches<'static>) -> Result<(), MigrationError> {
-        let config = crate::utils::load_config(matches)
-            .context(MigrationErrorKind::OtherError("Failed to load config"))?;
+        let config = crate::utils::load_config(matches).context("Failed to load config")?;
+        if let Some(schemas) = tenant_schemas(matches)? {
+            return migrate_tenants(matches, &config, &schemas).await;
+        }
+        if let Some(urls) = crate::utils::target_urls(&config)? {
+            return migrate_targets(matches, &config, urls).await;
+        }
         let mut connect = crate::utils::connect(&config).await?;
 
         for one_change_sets in config.changesets.iter() {
@@ -41,20 +122,169 @@ impl Command for MigrateCommand {
             let local_changesets = MigrationChangeSets::load_dir(
                 &one_change_sets.group_name,
                 &one_change_sets.directory,
-            )
-            .context(MigrationErrorKind::OtherError(
-                "Failed to load migration SQLs",
-            ))?;
+            )?;
+            connect.options_mut().hooks = Hooks::load_dir(&one_change_sets.directory)?;
             //println!("Processing {}", one_change_sets.group_name);
 
-            connect
-                .migrate(
-                    &local_changesets,
-                    matches.value_of("count").map(|x| x.parse().unwrap()),
-                )
-                .await?;
+            let count = matches.value_of("count").map(|x| x.parse().unwrap());
+            if matches.is_present("atomic") {
+                connect.migrate_atomic(&local_changesets, count).await?;
+            } else {
+                connect.migrate(&local_changesets, count).await?;
+            }
         }
 
         Ok(())
     }
 }
+
+fn tenant_schemas(matches: &ArgMatches<'static>) -> Result<Option<TenantSchemas>, MigrationError> {
+    if let Some(tenants) = matches.values_of("tenant") {
+        Ok(Some(TenantSchemas::List(
+            tenants.map(|x| x.to_string()).collect(),
+        )))
+    } else if let Some(sql) = matches.value_of("tenant-query") {
+        Ok(Some(TenantSchemas::Query(sql.to_string())))
+    } else if let Some(path) = matches.value_of("tenants-file") {
+        let text = fs::read_to_string(path).context(&format!("Cannot read {}", path))?;
+        Ok(Some(TenantSchemas::List(
+            text.lines()
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_string())
+                .collect(),
+        )))
+    } else {
+        Ok(None)
+    }
+}
+
+async fn migrate_tenants(
+    matches: &ArgMatches<'static>,
+    config: &crate::utils::MigrationConfig,
+    schemas: &TenantSchemas,
+) -> Result<(), MigrationError> {
+    let url = config.database_url.as_deref().unwrap_or("");
+    let parallelism = match matches.value_of("parallel") {
+        Some(x) => x.parse()?,
+        None => 1,
+    };
+    let count = matches.value_of("count").map(|x| x.parse()).transpose()?;
+    let options = crate::utils::migration_options(config)?;
+    let schemas = TenantMigrator::new(url, options.clone())
+        .load_schemas(schemas)
+        .await?;
+
+    let mut failed: Vec<String> = Vec::new();
+    for one_change_sets in config.changesets.iter() {
+        if let Some(target_group_name) = matches.value_of("group_name") {
+            if target_group_name != one_change_sets.group_name {
+                continue;
+            }
+        }
+        println!("loading {}", one_change_sets.directory);
+        let local_changesets =
+            MigrationChangeSets::load_dir(&one_change_sets.group_name, &one_change_sets.directory)?;
+        let mut options = options.clone();
+        options.hooks = Hooks::load_dir(&one_change_sets.directory)?;
+        let summary = TenantMigrator::new(url, options)
+            .parallelism(parallelism)
+            .atomic(matches.is_present("atomic"))
+            .migrate(&schemas, &local_changesets, count)
+            .await;
+        println!(
+            "{}: {} succeeded, {} failed",
+            one_change_sets.group_name,
+            summary.succeeded.len(),
+            summary.failed.len()
+        );
+        for (schema, _) in summary.failed.iter() {
+            if !failed.contains(schema) {
+                failed.push(schema.clone());
+            }
+        }
+    }
+
+    if let Some(path) = matches.value_of("failed-file") {
+        let text: String = failed.iter().map(|x| format!("{}\n", x)).collect();
+        fs::write(path, text).context(&format!("Cannot write {}", path))?;
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(MigrationError::TenantError {
+            failed,
+            total: schemas.len(),
+        })
+    }
+}
+
+async fn migrate_targets(
+    matches: &ArgMatches<'static>,
+    config: &crate::utils::MigrationConfig,
+    urls: Vec<String>,
+) -> Result<(), MigrationError> {
+    let concurrency = match matches.value_of("concurrency") {
+        Some(x) => x.parse()?,
+        None => 1,
+    };
+    let count = matches.value_of("count").map(|x| x.parse()).transpose()?;
+    let options = crate::utils::migration_options(config)?;
+    let total = urls.len();
+
+    let mut failed: Vec<String> = Vec::new();
+    for one_change_sets in config.changesets.iter() {
+        if let Some(target_group_name) = matches.value_of("group_name") {
+            if target_group_name != one_change_sets.group_name {
+                continue;
+            }
+        }
+        println!("loading {}", one_change_sets.directory);
+        let local_changesets =
+            MigrationChangeSets::load_dir(&one_change_sets.group_name, &one_change_sets.directory)?;
+        let mut options = options.clone();
+        options.hooks = Hooks::load_dir(&one_change_sets.directory)?;
+        let report = FanOutMigrator::new(urls.clone(), options)
+            .concurrency(concurrency)
+            .fail_fast(matches.is_present("fail-fast"))
+            .atomic(matches.is_present("atomic"))
+            .migrate(&local_changesets, count)
+            .await?;
+
+        println!(
+            "plan for {}: {}",
+            one_change_sets.group_name,
+            if report.plan.is_empty() {
+                "up to date".to_string()
+            } else {
+                report
+                    .plan
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        );
+        for one in report.targets.iter() {
+            match &one.status {
+                TargetStatus::Migrated => println!("  migrated  {}", one.target),
+                TargetStatus::Failed(e) => println!("  failed    {}: {}", one.target, e),
+                TargetStatus::Skipped => println!("  skipped   {}", one.target),
+            }
+        }
+        for target in report.failed_targets() {
+            if !failed.contains(&target) {
+                failed.push(target);
+            }
+        }
+        if !report.is_success() && matches.is_present("fail-fast") {
+            break;
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(MigrationError::TargetError { failed, total })
+    }
+}
@@ -1,7 +1,12 @@
+mod baseline;
 mod generate;
+mod history;
+mod mark_applied;
+mod mark_reverted;
 mod migrate;
 mod override_undo_sql;
 mod redo;
+mod repair;
 mod rollback;
 mod setup;
 
@@ -15,6 +20,11 @@ pub(crate) const COMMANDS: &[&dyn Command] = &[
     &override_undo_sql::UpdateRollbackSqlCommand,
     &setup::SetupCommand,
     &redo::RedoCommand,
+    &history::HistoryCommand,
+    &baseline::BaselineCommand,
+    &mark_applied::MarkAppliedCommand,
+    &mark_reverted::MarkRevertedCommand,
+    &repair::RepairCommand,
 ];
 
 #[async_trait]
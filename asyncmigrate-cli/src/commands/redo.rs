@@ -1,6 +1,6 @@
 use super::Command;
 use async_trait::async_trait;
-use asyncmigrate::{Migration, MigrationChangeSets, MigrationError};
+use asyncmigrate::{Hooks, Migration, MigrationChangeSets, MigrationError};
 use clap::{App, Arg, ArgMatches};
 
 pub struct RedoCommand;
@@ -11,45 +11,40 @@ impl Command for RedoCommand {
         "redo"
     }
     fn config_subcommand(&self, app: App<'static, 'static>) -> App<'static, 'static> {
-        crate::utils::common_args(app.about("Rollback database"))
-            .arg(
-                Arg::with_name("group_name")
-                    .index(1)
-                    .help("Target group name")
-                    .takes_value(true)
-                    .required(true),
-            )
-            .arg(
-                Arg::with_name("count")
-                    .index(2)
-                    .help("# of change sets to apply")
-                    .takes_value(true)
-                    .default_value("1"),
-            )
+        crate::utils::common_args(
+            app.about("Revert and apply again change sets in one transaction"),
+        )
+        .arg(
+            Arg::with_name("group_name")
+                .index(1)
+                .help("Target group name")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("count")
+                .index(2)
+                .help("# of change sets to redo")
+                .takes_value(true)
+                .default_value("1"),
+        )
     }
     async fn run(&self, matches: &ArgMatches<'static>) -> Result<(), MigrationError> {
         let config = crate::utils::load_config(matches)?;
         let mut connect = crate::utils::connect(&config).await?;
 
+        let group_name = matches.value_of("group_name").unwrap();
         let count = matches.value_of("count").unwrap().parse()?;
+        let one_change_sets = config
+            .changesets
+            .iter()
+            .find(|x| x.group_name == group_name)
+            .ok_or_else(|| MigrationError::UnknownGroupError(group_name.to_string()))?;
+        let local_changesets =
+            MigrationChangeSets::load_dir(&one_change_sets.group_name, &one_change_sets.directory)?;
+        connect.options_mut().hooks = Hooks::load_dir(&one_change_sets.directory)?;
 
-        connect
-            .rollback(matches.value_of("group_name").unwrap(), Some(count))
-            .await?;
-
-        for one_change_sets in config.changesets.iter() {
-            if let Some(target_group_name) = matches.value_of("group_name") {
-                if target_group_name != one_change_sets.group_name {
-                    continue;
-                }
-            }
-            let local_changesets = MigrationChangeSets::load_dir(
-                &one_change_sets.group_name,
-                &one_change_sets.directory,
-            )?;
-
-            connect.migrate(&local_changesets, Some(count)).await?;
-        }
+        connect.redo(&local_changesets, count).await?;
 
         Ok(())
     }
@@ -0,0 +1,120 @@
+use super::Command;
+use crate::utils::ResultExt;
+use async_trait::async_trait;
+use asyncmigrate::{Migration, MigrationChangeSets, MigrationError, RepairAction};
+use clap::{App, Arg, ArgMatches};
+use std::io::{self, BufRead, Write};
+
+pub struct RepairCommand;
+
+#[async_trait]
+impl Command for RepairCommand {
+    fn command_name(&self) -> &'static str {
+        "repair"
+    }
+    fn config_subcommand(&self, app: App<'static, 'static>) -> App<'static, 'static> {
+        crate::utils::common_args(app.about("Reconcile history with local files"))
+            .arg(
+                Arg::with_name("group_name")
+                    .index(1)
+                    .help("Target group name")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("version")
+                    .index(2)
+                    .help("Versions to repair. All versions if omitted")
+                    .takes_value(true)
+                    .multiple(true),
+            )
+            .arg(
+                Arg::with_name("yes")
+                    .short("y")
+                    .long("yes")
+                    .help("Repair without confirmation"),
+            )
+    }
+    async fn run(&self, matches: &ArgMatches<'static>) -> Result<(), MigrationError> {
+        let config = crate::utils::load_config(matches).context("Failed to load config")?;
+        let mut connect = crate::utils::connect(&config).await?;
+
+        let group_name = matches.value_of("group_name").unwrap();
+        let versions = match matches.values_of("version") {
+            Some(values) => Some(values.map(|x| x.parse()).collect::<Result<Vec<i32>, _>>()?),
+            None => None,
+        };
+        let one_change_sets = config
+            .changesets
+            .iter()
+            .find(|x| x.group_name == group_name)
+            .ok_or_else(|| MigrationError::UnknownGroupError(group_name.to_string()))?;
+        let local_changesets =
+            MigrationChangeSets::load_dir(&one_change_sets.group_name, &one_change_sets.directory)?;
+
+        let actions = connect
+            .plan_repair(&local_changesets, versions.as_deref())
+            .await?;
+        if actions.is_empty() {
+            println!("Nothing to repair");
+            return Ok(());
+        }
+        for one in actions.iter() {
+            print_action(one);
+        }
+
+        if !matches.is_present("yes") && !confirm("Repair history?")? {
+            println!("Canceled");
+            return Ok(());
+        }
+        // fail if history or local files changed since the confirmation
+        connect.options_mut().expected_repair = Some(actions);
+        connect
+            .repair(&local_changesets, versions.as_deref())
+            .await?;
+        Ok(())
+    }
+}
+
+fn print_action(action: &RepairAction) {
+    match action {
+        RepairAction::Update { stored, local } => {
+            println!("update {}", stored.name);
+            if stored.name != local.name {
+                println!("  name: {} -> {}", stored.name.name, local.name.name);
+            }
+            if stored.up_sql != local.up_sql {
+                println!("  up SQL:");
+                crate::utils::print_diff(&stored.up_sql, &local.up_sql);
+            }
+            if stored.down_sql != local.down_sql {
+                println!("  down SQL:");
+                crate::utils::print_diff(
+                    stored.down_sql.as_deref().unwrap_or(""),
+                    local.down_sql.as_deref().unwrap_or(""),
+                );
+            }
+        }
+        RepairAction::Remove(stored) => {
+            println!("remove {} (not found in local files)", stored.name);
+        }
+        RepairAction::Resolve(stored) => {
+            let applied = stored.applied.clone().unwrap_or_default();
+            println!("resolve {} ({} -> applied)", stored.name, applied.status);
+            if let Some(index) = applied.failed_statement {
+                println!("  failed statement: {}", index + 1);
+            }
+            if let Some(error) = applied.error {
+                println!("  error: {}", error);
+            }
+        }
+    }
+}
+
+fn confirm(message: &str) -> Result<bool, MigrationError> {
+    print!("{} [y/N] ", message);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
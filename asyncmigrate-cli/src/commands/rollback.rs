@@ -1,6 +1,6 @@
 use super::Command;
 use async_trait::async_trait;
-use asyncmigrate::{Migration, MigrationError};
+use asyncmigrate::{Hooks, Migration, MigrationChangeSets, MigrationError};
 use clap::{App, Arg, ArgMatches};
 
 pub struct RollbackCommand;
@@ -26,17 +26,80 @@ impl Command for RollbackCommand {
                     .takes_value(true)
                     .required(true),
             )
+            .arg(
+                Arg::with_name("allow-irreversible")
+                    .long("allow-irreversible")
+                    .help(
+                        "Remove change sets without down SQL from history without reverting them",
+                    ),
+            )
+            .arg(
+                Arg::with_name("source")
+                    .long("source")
+                    .help("Where to take down SQL from")
+                    .takes_value(true)
+                    .possible_values(&["stored", "local", "prefer-local"]),
+            )
+            .arg(
+                Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Show change sets to revert without reverting them"),
+            )
     }
     async fn run(&self, matches: &ArgMatches<'static>) -> Result<(), MigrationError> {
         let config = crate::utils::load_config(matches)?;
         let mut connect = crate::utils::connect(&config).await?;
+        connect.options_mut().allow_irreversible = matches.is_present("allow-irreversible");
 
-        connect
-            .rollback(
-                matches.value_of("group_name").unwrap(),
-                Some(matches.value_of("count").unwrap().parse()?),
-            )
-            .await?;
+        let group_name = matches.value_of("group_name").unwrap();
+        let count = Some(matches.value_of("count").unwrap().parse()?);
+        let one_change_sets = config
+            .changesets
+            .iter()
+            .find(|x| x.group_name == group_name);
+        if let Some(one_change_sets) = one_change_sets {
+            connect.options_mut().hooks = Hooks::load_dir(&one_change_sets.directory)?;
+        }
+        let local_changesets = match matches.value_of("source") {
+            Some(source) => {
+                connect.options_mut().rollback_source = source.parse()?;
+                let one_change_sets = one_change_sets
+                    .ok_or_else(|| MigrationError::UnknownGroupError(group_name.to_string()))?;
+                Some(MigrationChangeSets::load_dir(
+                    &one_change_sets.group_name,
+                    &one_change_sets.directory,
+                )?)
+            }
+            None => None,
+        };
+
+        let stored = connect.plan_rollback(group_name, count).await?;
+        let plan = match local_changesets.as_ref() {
+            Some(local) => connect.plan_rollback_with_local(local, count).await?,
+            None => stored.clone(),
+        };
+        for (stored, one) in stored.iter().zip(plan.iter()) {
+            if one.is_reversible() {
+                println!("revert {}", one.name);
+            } else {
+                println!("remove {} from history (irreversible, no-op)", one.name);
+            }
+            if stored.down_sql != one.down_sql {
+                println!("  down SQL (stored -> local):");
+                crate::utils::print_diff(
+                    stored.down_sql.as_deref().unwrap_or(""),
+                    one.down_sql.as_deref().unwrap_or(""),
+                );
+            }
+        }
+        if matches.is_present("dry-run") {
+            return Ok(());
+        }
+
+        match local_changesets.as_ref() {
+            Some(local) => connect.rollback_with_local(local, count).await?,
+            None => connect.rollback(group_name, count).await?,
+        }
         Ok(())
     }
 }
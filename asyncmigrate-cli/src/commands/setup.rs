@@ -1,8 +1,8 @@
 use super::Command;
+use crate::utils::ResultExt;
 use async_trait::async_trait;
-use asyncmigrate::{MigrationError, MigrationErrorKind};
+use asyncmigrate::MigrationError;
 use clap::{App, Arg, ArgMatches};
-use failure::ResultExt;
 use rustyline::Editor;
 use std::fs;
 use std::io::Write;
@@ -27,27 +27,27 @@ impl Command for SetupCommand {
         let mut rl = Editor::<()>::new();
         let host = rl
             .readline_with_initial("PostgreSQL host: ", ("localhost", ""))
-            .context(MigrationErrorKind::OtherError("Cannot get host"))?;
+            .context("Cannot get host")?;
         rl.clear_history();
         let port = rl
             .readline_with_initial("PostgreSQL port: ", ("5432", ""))
-            .context(MigrationErrorKind::OtherError("Cannot get host"))?;
+            .context("Cannot get port")?;
         rl.clear_history();
         let dbname = rl
             .readline_with_initial("PostgreSQL database name: ", ("postgres", ""))
-            .context(MigrationErrorKind::OtherError("Cannot get database name"))?;
+            .context("Cannot get database name")?;
         rl.clear_history();
         let user = rl
             .readline_with_initial("PostgreSQL user: ", ("postgres", ""))
-            .context(MigrationErrorKind::OtherError("Cannot get user"))?;
-        let pass = rpassword::read_password_from_tty(Some("Password: "))
-            .context(MigrationErrorKind::OtherError("Cannot get password"))?;
+            .context("Cannot get user")?;
+        let pass =
+            rpassword::read_password_from_tty(Some("Password: ")).context("Cannot get password")?;
         let connection_url = format!("postgres://{}:{}@{}:{}/{}", user, pass, host, port, dbname);
 
         rl.clear_history();
         let group_name = rl
             .readline_with_initial("Migration group: ", ("default", ""))
-            .context(MigrationErrorKind::OtherError("Cannot get migration group"))?;
+            .context("Cannot get migration group")?;
 
         let initialize_directory = PathBuf::from(matches.value_of("directory").unwrap_or("."));
         let mut migration_group_path = initialize_directory.clone();
@@ -64,10 +64,10 @@ impl Command for SetupCommand {
                 directory: format!("./{}", group_name),
                 group_name,
             }],
+            ..Default::default()
         };
 
-        serde_json::to_writer_pretty(config_writer, &config)
-            .context(MigrationErrorKind::OtherError("serialize config error"))?;
+        serde_json::to_writer_pretty(config_writer, &config).context("serialize config error")?;
 
         fs::File::create(migration_group_path.join("1__start__up.sql"))?
             .write_all(b"CREATE TABLE start_table(id INTEGER PRIMARY KEY);\n")?;
@@ -4,7 +4,14 @@ pub mod utils;
 use clap::{crate_authors, crate_version, App, AppSettings};
 
 #[tokio::main]
-async fn main() -> Result<(), asyncmigrate::MigrationError> {
+async fn main() {
+    if let Err(e) = run().await {
+        utils::print_error(&e);
+        std::process::exit(1);
+    }
+}
+
+async fn run() -> Result<(), asyncmigrate::MigrationError> {
     let matches = App::new("database migration")
         .version(crate_version!())
         .author(crate_authors!())
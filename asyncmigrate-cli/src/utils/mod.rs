@@ -1,9 +1,13 @@
-use asyncmigrate::{Connection, MigrationError, MigrationErrorKind};
+use asyncmigrate::{
+    Connection, HistoryOperation, MigrationError, MigrationEvent, MigrationOptions, Migrator,
+};
 use clap::{App, Arg, ArgMatches};
-use failure::ResultExt;
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::io;
 use std::path::Path;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub fn common_args(app: App<'static, 'static>) -> App<'static, 'static> {
     app.arg(
@@ -11,7 +15,7 @@ pub fn common_args(app: App<'static, 'static>) -> App<'static, 'static> {
             .short("u")
             .long("url")
             .takes_value(true)
-            .help("Database connection URL"),
+            .help("Database connection URL or libpq connection string"),
     )
     .arg(
         Arg::with_name("config")
@@ -20,11 +24,37 @@ pub fn common_args(app: App<'static, 'static>) -> App<'static, 'static> {
             .takes_value(true)
             .help("Configuration file path"),
     )
+    .arg(
+        Arg::with_name("lock-timeout")
+            .long("lock-timeout")
+            .takes_value(true)
+            .help("Seconds to wait for another migrator to release the lock"),
+    )
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct MigrationConfig {
     pub database_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_urls: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_urls_command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_schema: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_table: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migration_lock_timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_timeout_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statement_timeout_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_retries: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_retry_interval_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sql_comparison: Option<String>,
     pub changesets: Vec<MigrationConfigSet>,
 }
 
@@ -37,7 +67,20 @@ pub struct MigrationConfigSet {
 pub fn load_config(matches: &ArgMatches<'static>) -> Result<MigrationConfig, MigrationError> {
     let mut loaded_config = load_config_try(matches)?;
     if let Some(db_url) = matches.value_of("url") {
-        loaded_config.database_url = Some(db_url.to_string())
+        // a URL on the command line migrates only that database
+        loaded_config.database_url = Some(db_url.to_string());
+        loaded_config.database_urls = None;
+        loaded_config.database_urls_command = None;
+    }
+    if let Some(targets) = matches.values_of("target") {
+        loaded_config.database_urls = Some(targets.map(|x| x.to_string()).collect());
+    }
+    if let Some(command) = matches.value_of("targets-command") {
+        loaded_config.database_urls = None;
+        loaded_config.database_urls_command = Some(command.to_string());
+    }
+    if let Some(timeout) = matches.value_of("lock-timeout") {
+        loaded_config.migration_lock_timeout = Some(timeout.parse()?);
     }
     Ok(loaded_config)
 }
@@ -48,24 +91,17 @@ fn load_config_try(matches: &ArgMatches<'static>) -> Result<MigrationConfig, Mig
         (
             path,
             serde_json::from_reader(fs::File::open(path)?)
-                .context(MigrationErrorKind::OtherError("Cannot parse config"))?,
+                .context(&format!("Cannot parse config {}", path))?,
         )
     } else if let Ok(file) = fs::File::open("dbmigration.json") {
         //println!("loading config from current directory");
         (
             "./dbmigration.json",
-            serde_json::from_reader(file)
-                .context(MigrationErrorKind::OtherError("Cannot parse config"))?,
+            serde_json::from_reader(file).context("Cannot parse config ./dbmigration.json")?,
         )
     } else {
         //println!("config file is not found");
-        (
-            "./dbmigration.json",
-            MigrationConfig {
-                database_url: None,
-                changesets: vec![],
-            },
-        )
+        ("./dbmigration.json", MigrationConfig::default())
     };
 
     let base_path = Path::new(config_path)
@@ -80,10 +116,273 @@ fn load_config_try(matches: &ArgMatches<'static>) -> Result<MigrationConfig, Mig
     Ok(migration_config)
 }
 
-pub async fn connect(config: &MigrationConfig) -> Result<Connection, MigrationError> {
-    let url = config
-        .database_url
-        .as_ref()
-        .ok_or(MigrationErrorKind::OtherError("No connection URL"))?;
-    asyncmigrate::connect(url).await
+pub async fn connect(config: &MigrationConfig) -> Result<Migrator<Connection>, MigrationError> {
+    let options = migration_options(config)?;
+    // Without URL, connect with PG* environment variables like psql does
+    let connection = asyncmigrate::connect_with_observers(
+        config.database_url.as_deref().unwrap_or(""),
+        options.observers.clone(),
+    )
+    .await?;
+    Ok(Migrator::with_options(connection, options))
+}
+
+/**
+ * Database URLs to fan out to, from `database_urls` or the output of
+ * `database_urls_command`, one per line. `None` if neither is configured.
+ */
+pub fn target_urls(config: &MigrationConfig) -> Result<Option<Vec<String>>, MigrationError> {
+    if let Some(urls) = config.database_urls.as_ref() {
+        return Ok(Some(urls.clone()));
+    }
+    let command = match config.database_urls_command.as_ref() {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let context = format!("Cannot discover targets with `{}`", command);
+    let output = process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stderr(process::Stdio::inherit())
+        .output()
+        .context(&context)?;
+    if !output.status.success() {
+        return Err(MigrationError::context(
+            &context,
+            io::Error::other(output.status.to_string()),
+        ));
+    }
+    Ok(Some(
+        String::from_utf8(output.stdout)
+            .context(&context)?
+            .lines()
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .collect(),
+    ))
+}
+
+/**
+ * Migration options from the config, printing progress to the console.
+ */
+pub fn migration_options(config: &MigrationConfig) -> Result<MigrationOptions, MigrationError> {
+    let mut options = MigrationOptions::default().observer(print_event);
+    if let Some(schema) = config.history_schema.as_ref() {
+        options = options.history_schema(schema);
+    }
+    if let Some(table) = config.history_table.as_ref() {
+        options = options.history_table(table);
+    }
+    if let Some(timeout) = config.migration_lock_timeout {
+        options = options.migration_lock_timeout(Duration::from_secs(timeout));
+    }
+    if let Some(timeout) = config.lock_timeout_ms {
+        options = options.lock_timeout(Duration::from_millis(timeout));
+    }
+    if let Some(timeout) = config.statement_timeout_ms {
+        options = options.statement_timeout(Duration::from_millis(timeout));
+    }
+    if let Some(retries) = config.lock_retries {
+        let interval = config
+            .lock_retry_interval_ms
+            .map(Duration::from_millis)
+            .unwrap_or(options.lock_retry_interval);
+        options = options.lock_retries(retries, interval);
+    }
+    if let Some(comparison) = config.sql_comparison.as_ref() {
+        options = options.sql_comparison(comparison.parse()?);
+    }
+    Ok(options)
+}
+
+/**
+ * Print progress of a migration to the console.
+ */
+fn print_event(event: &MigrationEvent) {
+    match event {
+        MigrationEvent::StepFinished {
+            operation,
+            change_set,
+            ..
+        } => match operation {
+            HistoryOperation::Rollback => println!("revert: {}", change_set),
+            _ => println!("{}: {}", operation, change_set),
+        },
+        MigrationEvent::Skipped { change_set, .. } => {
+            println!("remove from history: {}", change_set)
+        }
+        MigrationEvent::Recorded {
+            operation,
+            change_set,
+            ..
+        } => println!("{}: {}", operation.as_str().replace('_', " "), change_set),
+        MigrationEvent::Failed {
+            change_set: Some(change_set),
+            statement,
+            error,
+            ..
+        } => match statement {
+            Some(statement) => eprintln!(
+                "failed: {} at statement {}: {}",
+                change_set,
+                statement + 1,
+                error
+            ),
+            None => eprintln!("failed: {}: {}", change_set, error),
+        },
+        MigrationEvent::Failed {
+            operation,
+            change_set: None,
+            error,
+            ..
+        } => eprintln!("{} failed: {}", operation, error),
+        MigrationEvent::Retrying {
+            change_set,
+            attempt,
+            retries,
+            wait,
+            ..
+        } => println!(
+            "lock timeout: {} (retry {}/{} after {:?})",
+            change_set, attempt, retries, wait
+        ),
+        MigrationEvent::RolledBack { group_name } => {
+            println!("rolled back all changes of {}", group_name)
+        }
+        MigrationEvent::LockWaiting => {
+            println!("waiting for another migrator to release the lock")
+        }
+        MigrationEvent::ConnectionError { error } => eprintln!("connection error: {}", error),
+        MigrationEvent::PasswordFileIgnored { path } => eprintln!(
+            "WARNING: password file \"{}\" has group or world access; permissions should be u=rw (0600) or less",
+            path.display()
+        ),
+        MigrationEvent::TenantStarted { schema } => println!("tenant {}: started", schema),
+        MigrationEvent::TenantFinished {
+            schema,
+            error: None,
+        } => println!("tenant {}: done", schema),
+        MigrationEvent::TenantFinished {
+            schema,
+            error: Some(error),
+        } => eprintln!("tenant {}: failed: {}", schema, error),
+        MigrationEvent::TargetStarted { target } => println!("{}: started", target),
+        MigrationEvent::TargetFinished {
+            target,
+            error: Some(error),
+        } => eprintln!("{}: failed: {}", target, error),
+        _ => (),
+    }
+}
+
+/**
+ * Format time as `YYYY-MM-DD hh:mm:ss` in UTC.
+ */
+pub fn format_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or(0);
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // civil date from days since 1970-01-01
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/**
+ * Print line by line difference of two texts.
+ */
+pub fn print_diff(old: &str, new: &str) {
+    let old: Vec<_> = old.lines().collect();
+    let new: Vec<_> = new.lines().collect();
+
+    // longest common subsequence table
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            println!("    {}", old[i]);
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            println!("  - {}", old[i]);
+            i += 1;
+        } else {
+            println!("  + {}", new[j]);
+            j += 1;
+        }
+    }
+}
+
+/**
+ * Print an error with its code. A failed statement is shown with a caret
+ * under the error location.
+ */
+pub fn print_error(error: &MigrationError) {
+    eprintln!("error[{}]: {}", error.code(), error);
+    if let MigrationError::StatementError { failure, .. } = error {
+        match (&failure.location, &failure.statement) {
+            (Some(location), _) => {
+                let line = location.line.to_string();
+                let caret = location.caret();
+                let (text, marker) = caret.split_once('\n').unwrap_or((&caret, ""));
+                eprintln!("{} | {}", line, text);
+                eprintln!("{} | {}", " ".repeat(line.len()), marker);
+            }
+            (None, Some(statement)) => eprintln!("  = statement: {}", statement),
+            (None, None) => (),
+        }
+        eprintln!("  = SQLSTATE: {}", failure.sql_state);
+        if let Some(detail) = failure.detail.as_ref() {
+            eprintln!("  = detail: {}", detail);
+        }
+        if let Some(hint) = failure.hint.as_ref() {
+            eprintln!("  = hint: {}", hint);
+        }
+    }
+}
+
+/**
+ * Wrap an error with a description of what failed, keeping it as the source.
+ */
+pub trait ResultExt<T> {
+    fn context(self, context: &str) -> Result<T, MigrationError>;
+}
+
+impl<T, E> ResultExt<T> for Result<T, E>
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    fn context(self, context: &str) -> Result<T, MigrationError> {
+        self.map_err(|e| MigrationError::context(context, e))
+    }
 }
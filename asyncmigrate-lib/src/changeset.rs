@@ -6,10 +6,14 @@ use std::collections::HashMap;
 use std::fmt;
 use std::fs;
 use std::io::{self, prelude::*};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str;
+use std::time::Duration;
 
-use crate::MigrationError;
+use crate::error::diff_snippet;
+use crate::{AppliedInfo, ApplyStatus, RollbackSource, SqlComparison};
+use crate::{MigrationError, MismatchKind};
+use sha2::{Digest, Sha256};
 
 /**
  * change sets for migration
@@ -30,36 +34,44 @@ pub struct MigrationChangeSets {
 impl MigrationChangeSets {
     /**
      * Load change set from directory
+     *
+     * Both `VERSION__NAME/up.sql` and `VERSION__NAME__up.sql` layouts are
+     * accepted.
      */
     pub fn load_dir<P: AsRef<Path>>(
         name: &str,
         path: P,
     ) -> Result<MigrationChangeSets, MigrationError> {
-        MigrationChangeSets::load(
-            name,
-            fs::read_dir(path)?.flat_map(|x| x.ok()).flat_map(|x| {
-                if let Ok(m) = x.metadata() {
-                    if m.is_dir() {
-                        None
-                    } else if let Some(y) = x.path().to_str() {
-                        Some(Cow::Owned(y.to_string()))
-                    } else {
-                        None
+        let base_path = path.as_ref();
+        let mut filenames = Vec::new();
+        let read_dir = |path: &Path| {
+            fs::read_dir(path).map_err(|source| MigrationError::FileError {
+                path: path.to_path_buf(),
+                source,
+            })
+        };
+        for entry in read_dir(base_path)?.flat_map(|x| x.ok()) {
+            let entry_name = entry.file_name().to_string_lossy().to_string();
+            if entry.metadata()?.is_dir() {
+                for child in read_dir(&entry.path())?.flat_map(|x| x.ok()) {
+                    if child.metadata()?.is_file() {
+                        filenames.push(Cow::Owned(format!(
+                            "{}/{}",
+                            entry_name,
+                            child.file_name().to_string_lossy()
+                        )));
                     }
-                } else {
-                    None
                 }
-            }),
-            |x| match fs::File::open(x).map(|mut y| {
-                let mut buffer = Vec::new();
-                y.read_to_end(&mut buffer)?;
-                Ok(buffer)
-            }) {
-                Ok(Ok(x)) => Ok(Cow::Owned(x)),
-                Ok(Err(e)) => Err(e),
-                Err(e) => Err(e),
-            },
-        )
+            } else {
+                filenames.push(Cow::Owned(entry_name));
+            }
+        }
+
+        MigrationChangeSets::load(name, base_path, filenames.into_iter(), |x| {
+            let mut buffer = Vec::new();
+            fs::File::open(base_path.join(x))?.read_to_end(&mut buffer)?;
+            Ok(Cow::Owned(buffer))
+        })
     }
 
     /**
@@ -80,16 +92,18 @@ impl MigrationChangeSets {
         name: &str,
         _asset: A,
     ) -> Result<MigrationChangeSets, MigrationError> {
-        MigrationChangeSets::load(name, A::iter(), |x| {
+        MigrationChangeSets::load(name, Path::new(""), A::iter(), |x| {
             A::get(x).ok_or_else(|| io::ErrorKind::NotFound.into())
         })
     }
 
     /**
      * Load change sets from filename iterator and file data read function.
+     * Filenames are relative to `base_path`.
      */
     fn load<I, G>(
         name: &str,
+        base_path: &Path,
         filenames: I,
         get_data: G,
     ) -> Result<MigrationChangeSets, MigrationError>
@@ -97,19 +111,29 @@ impl MigrationChangeSets {
         I: Iterator<Item = Cow<'static, str>>,
         G: Fn(&str) -> Result<Cow<'static, [u8]>, io::Error>,
     {
-        let mut up_sql: HashMap<i32, (ChangeSetVersionName, String)> = HashMap::new();
+        let mut up_sql: HashMap<i32, (ChangeSetVersionName, String, PathBuf)> = HashMap::new();
         let mut down_sql: HashMap<i32, (ChangeSetVersionName, String)> = HashMap::new();
+        let read = |entry: &str| {
+            get_data(entry).map_err(|source| MigrationError::FileError {
+                path: base_path.join(entry),
+                source,
+            })
+        };
         for entry in filenames {
             match ParsedName::parse(entry.as_ref()) {
                 Some(ParsedName::Up(x)) => {
-                    let data = get_data(entry.as_ref())?;
+                    let data = read(entry.as_ref())?;
                     up_sql.insert(
                         x.version,
-                        (x, str::from_utf8(data.as_ref()).unwrap().to_string()),
+                        (
+                            x,
+                            str::from_utf8(data.as_ref()).unwrap().to_string(),
+                            base_path.join(entry.as_ref()),
+                        ),
                     );
                 }
                 Some(ParsedName::Down(x)) => {
-                    let data = get_data(entry.as_ref())?;
+                    let data = read(entry.as_ref())?;
                     down_sql.insert(
                         x.version,
                         (x, str::from_utf8(data.as_ref()).unwrap().to_string()),
@@ -119,14 +143,27 @@ impl MigrationChangeSets {
             }
         }
 
-        let mut change_sets: Vec<_> = up_sql
-            .into_iter()
-            .map(|(k, v)| ChangeSet {
+        let mut change_sets = Vec::new();
+        for (k, v) in up_sql.into_iter() {
+            let options = ChangeSetOptions::parse(&v.1).map_err(|e| match e {
+                MigrationError::DirectiveError {
+                    path: None,
+                    message,
+                } => MigrationError::DirectiveError {
+                    path: Some(v.2.clone()),
+                    message,
+                },
+                e => e,
+            })?;
+            change_sets.push(ChangeSet {
+                options,
                 name: v.0,
                 up_sql: v.1,
                 down_sql: down_sql.remove(&k).map(|x| x.1),
-            })
-            .collect();
+                applied: None,
+                path: Some(v.2),
+            });
+        }
         change_sets.sort();
 
         Ok(MigrationChangeSets {
@@ -148,61 +185,287 @@ impl MigrationChangeSets {
         }
     }
 
+    /**
+     * Change sets up to `up_to_version`, which are recorded as applied by
+     * baseline.
+     */
+    pub fn baseline_subset(
+        &self,
+        up_to_version: i32,
+    ) -> Result<MigrationChangeSets, MigrationError> {
+        let change_sets: Vec<_> = self
+            .change_sets
+            .iter()
+            .take_while(|x| x.name.version <= up_to_version)
+            .cloned()
+            .collect();
+        if change_sets.is_empty() {
+            return Err(MigrationError::NothingToBaselineError {
+                group_name: self.group_name.clone(),
+                up_to_version,
+            });
+        }
+        Ok(MigrationChangeSets {
+            group_name: self.group_name.clone(),
+            change_sets,
+        })
+    }
+
+    /**
+     * Change set of `version` to be marked as applied. It must be the next
+     * change set to apply over `applied_sets`.
+     */
+    pub(crate) fn next_change_set(
+        &self,
+        applied_sets: &MigrationChangeSets,
+        version: i32,
+        comparison: SqlComparison,
+    ) -> Result<ChangeSet, MigrationError> {
+        applied_sets.check_clean()?;
+        match self
+            .calc_diff_with(applied_sets, comparison)?
+            .change_sets
+            .first()
+        {
+            Some(next) if next.name.version == version => Ok(next.clone()),
+            next => Err(MigrationError::NotNextChangeSetError {
+                group_name: self.group_name.clone(),
+                version,
+                next: next.map(|x| x.name.clone()),
+            }),
+        }
+    }
+
+    /**
+     * Applied change set of `version` to be marked as reverted. It must be
+     * the last applied change set.
+     */
+    pub(crate) fn last_change_set(&self, version: i32) -> Result<ChangeSet, MigrationError> {
+        match self.change_sets.last() {
+            Some(last) if last.name.version == version => Ok(last.clone()),
+            last => Err(MigrationError::NotLastChangeSetError {
+                group_name: self.group_name.clone(),
+                version,
+                last: last.map(|x| x.name.clone()),
+            }),
+        }
+    }
+
+    /**
+     * Applied change sets reverted by rolling back `count` change sets, in
+     * order of reverting. All change sets if `count` is `None`.
+     */
+    pub(crate) fn rollback_targets(
+        &self,
+        count: Option<usize>,
+    ) -> Result<Vec<ChangeSet>, MigrationError> {
+        self.check_clean()?;
+        let count = count.unwrap_or(self.change_sets.len());
+        if self.change_sets.len() < count {
+            return Err(MigrationError::NothingToRevertError {
+                group_name: self.group_name.clone(),
+                requested: count,
+                applied: self.change_sets.len(),
+            });
+        }
+        Ok(self.change_sets.iter().rev().take(count).cloned().collect())
+    }
+
+    /**
+     * Take down SQL of applied change sets `targets` from this local change
+     * sets according to `source`. Versions and names of `targets` must
+     * match local ones.
+     */
+    pub(crate) fn resolve_down_sql(
+        &self,
+        targets: Vec<ChangeSet>,
+        source: RollbackSource,
+    ) -> Result<Vec<ChangeSet>, MigrationError> {
+        targets
+            .into_iter()
+            .map(|mut stored| {
+                let version = stored.name.version;
+                match self.change_sets.iter().find(|x| x.name.version == version) {
+                    Some(local) if local.name != stored.name => Err(self.mismatch_error(
+                        local,
+                        MismatchKind::Name,
+                        &stored.name.name,
+                        &local.name.name,
+                    )),
+                    Some(local) => {
+                        match source {
+                            RollbackSource::Stored => (),
+                            RollbackSource::Local => stored.down_sql = local.down_sql.clone(),
+                            RollbackSource::PreferLocal => {
+                                if local.down_sql.is_some() {
+                                    stored.down_sql = local.down_sql.clone();
+                                }
+                            }
+                        }
+                        Ok(stored)
+                    }
+                    None if source == RollbackSource::PreferLocal => Ok(stored),
+                    None => Err(MigrationError::MissingChangeSetError {
+                        group_name: self.group_name.clone(),
+                        name: stored.name,
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    /**
+     * Plan redo of the last `count` applied change sets with this local
+     * change sets. Returns applied change sets to revert, with down SQL
+     * chosen by `source`, and local change sets to apply again. Versions and
+     * names of both must match.
+     */
+    pub(crate) fn redo_plan(
+        &self,
+        applied_sets: &MigrationChangeSets,
+        count: usize,
+        source: RollbackSource,
+        comparison: SqlComparison,
+    ) -> Result<(Vec<ChangeSet>, Vec<ChangeSet>), MigrationError> {
+        let revert = self.resolve_down_sql(applied_sets.rollback_targets(Some(count))?, source)?;
+        let remaining = applied_sets.subset(..applied_sets.change_sets.len() - revert.len());
+        let reapply: Vec<_> = self
+            .calc_diff_with(&remaining, comparison)?
+            .change_sets
+            .into_iter()
+            .take(revert.len())
+            .collect();
+        for (i, stored) in revert.iter().rev().enumerate() {
+            if reapply.get(i).map(|x| &x.name) != Some(&stored.name) {
+                return Err(MigrationError::MissingChangeSetError {
+                    group_name: self.group_name.clone(),
+                    name: stored.name.clone(),
+                });
+            }
+        }
+        check_transactional(&self.group_name, &reapply)?;
+        Ok((revert, reapply))
+    }
+
+    /**
+     * Fail if an applied change set is not completely applied.
+     */
+    pub(crate) fn check_clean(&self) -> Result<(), MigrationError> {
+        for one in self.change_sets.iter() {
+            if let Some(applied) = one.applied.as_ref() {
+                if applied.status != ApplyStatus::Applied {
+                    return Err(MigrationError::DirtyError {
+                        group_name: self.group_name.clone(),
+                        version: one.name.version,
+                        status: applied.status,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
     /**
      * Calculate a difference from the other change sets.
      */
     pub fn calc_diff(
         &self,
         original_sets: &MigrationChangeSets,
+    ) -> Result<MigrationChangeSets, MigrationError> {
+        self.calc_diff_with(original_sets, SqlComparison::Exact)
+    }
+
+    /**
+     * Calculate a difference from the other change sets, comparing SQL by
+     * `comparison`.
+     */
+    pub fn calc_diff_with(
+        &self,
+        original_sets: &MigrationChangeSets,
+        comparison: SqlComparison,
     ) -> Result<MigrationChangeSets, MigrationError> {
         for one in self
             .change_sets
             .iter()
             .zip(original_sets.change_sets.iter())
         {
-            if one.0.name.version != one.1.name.version {
-                return Err(MigrationError::VersionMismatchError(
-                    one.0.name.version,
-                    one.1.name.version,
-                )
-                .into());
+            self.check_name(one.0, one.1)?;
+            if !comparison.same_sql(&one.0.up_sql, &one.1.up_sql) {
+                return Err(self.mismatch_error(
+                    one.0,
+                    MismatchKind::UpSql,
+                    &one.1.up_sql,
+                    &one.0.up_sql,
+                ));
             }
-            if one.0.name != one.1.name {
-                return Err(MigrationError::InconsistentMigrationError(
-                    "Mismatch name",
-                    one.0.name.version,
-                )
-                .into());
-            }
-            if one.0.up_sql != one.1.up_sql {
-                return Err(MigrationError::InconsistentMigrationError(
-                    "Up SQL mismatch",
-                    one.0.name.version,
-                )
-                .into());
-            }
-            if one.0.down_sql != one.1.down_sql {
-                return Err(MigrationError::InconsistentMigrationError(
-                    "Down SQL mismatch",
-                    one.0.name.version,
-                )
-                .into());
+            if !comparison.same_optional_sql(one.0.down_sql.as_deref(), one.1.down_sql.as_deref()) {
+                return Err(self.mismatch_error(
+                    one.0,
+                    MismatchKind::DownSql,
+                    one.1.down_sql.as_deref().unwrap_or_default(),
+                    one.0.down_sql.as_deref().unwrap_or_default(),
+                ));
             }
         }
         if self.change_sets.len() < original_sets.change_sets.len() {
-            return Err(MigrationError::InconsistentMigrationError(
-                "Some migration is not found in local files",
-                original_sets.change_sets[self.change_sets.len()]
+            return Err(MigrationError::MissingChangeSetError {
+                group_name: self.group_name.clone(),
+                name: original_sets.change_sets[self.change_sets.len()]
                     .name
-                    .version,
-            )
-            .into());
+                    .clone(),
+            });
         }
         Ok(MigrationChangeSets {
             group_name: self.group_name.to_string(),
             change_sets: self.change_sets[original_sets.change_sets.len()..].to_vec(),
         })
     }
+
+    /**
+     * Fail if version or name of `local` change set differs from `stored`
+     * one.
+     */
+    pub(crate) fn check_name(
+        &self,
+        local: &ChangeSet,
+        stored: &ChangeSet,
+    ) -> Result<(), MigrationError> {
+        if local.name.version != stored.name.version {
+            return Err(MigrationError::VersionMismatchError {
+                group_name: self.group_name.clone(),
+                local: local.name.version,
+                stored: stored.name.version,
+            });
+        }
+        if local.name != stored.name {
+            return Err(self.mismatch_error(
+                local,
+                MismatchKind::Name,
+                &stored.name.name,
+                &local.name.name,
+            ));
+        }
+        Ok(())
+    }
+
+    /**
+     * Error of `local` change set which differs from history.
+     */
+    fn mismatch_error(
+        &self,
+        local: &ChangeSet,
+        kind: MismatchKind,
+        stored: &str,
+        current: &str,
+    ) -> MigrationError {
+        MigrationError::ChangeSetMismatchError {
+            group_name: self.group_name.clone(),
+            version: local.name.version,
+            kind,
+            path: local.path.clone(),
+            diff: diff_snippet(stored, current),
+        }
+    }
 }
 
 /**
@@ -213,6 +476,173 @@ pub struct ChangeSet {
     pub name: ChangeSetVersionName,
     pub up_sql: String,
     pub down_sql: Option<String>,
+    pub options: ChangeSetOptions,
+
+    /**
+     * History of applying this change set. `None` for local change sets.
+     */
+    pub applied: Option<AppliedInfo>,
+
+    /**
+     * File of up SQL this change set is loaded from. `None` for change sets
+     * loaded from history.
+     */
+    pub path: Option<PathBuf>,
+}
+
+impl ChangeSet {
+    /**
+     * SHA-256 checksum of up SQL in hex
+     */
+    pub fn checksum(&self) -> String {
+        Sha256::digest(self.up_sql.as_bytes())
+            .iter()
+            .map(|x| format!("{:02x}", x))
+            .collect()
+    }
+
+    /**
+     * `true` if rolling back this change set reverts the schema. A change
+     * set without down SQL or marked as irreversible can only be removed
+     * from history.
+     */
+    pub fn is_reversible(&self) -> bool {
+        self.down_sql.is_some() && !self.options.irreversible
+    }
+}
+
+/**
+ * Fail if some of `change_sets` cannot be reverted.
+ */
+pub(crate) fn check_reversible(
+    group_name: &str,
+    change_sets: &[ChangeSet],
+) -> Result<(), MigrationError> {
+    let versions: Vec<i32> = change_sets
+        .iter()
+        .filter(|x| !x.is_reversible())
+        .map(|x| x.name.version)
+        .collect();
+    if versions.is_empty() {
+        Ok(())
+    } else {
+        Err(MigrationError::IrreversibleError {
+            group_name: group_name.to_string(),
+            versions,
+        })
+    }
+}
+
+/**
+ * Fail if some of `change_sets` cannot be applied in a transaction.
+ */
+pub(crate) fn check_transactional(
+    group_name: &str,
+    change_sets: &[ChangeSet],
+) -> Result<(), MigrationError> {
+    match change_sets.iter().find(|x| x.options.no_transaction) {
+        Some(one) => Err(MigrationError::NoTransactionError {
+            group_name: group_name.to_string(),
+            version: one.name.version,
+            path: one.path.clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/**
+ * Options of a change set.
+ *
+ * Options are written in up SQL as directive comments.
+ *
+ * ```sql
+ * -- asyncmigrate: lock_timeout = 5s
+ * -- asyncmigrate: statement_timeout = 10min
+ * -- asyncmigrate: no_transaction
+ * -- asyncmigrate: irreversible
+ * ALTER TABLE base_table ADD COLUMN note TEXT;
+ * ```
+ */
+#[derive(Debug, Clone, Default, PartialEq, PartialOrd, Ord, Eq)]
+pub struct ChangeSetOptions {
+    /**
+     * `lock_timeout` while applying this change set. Overrides the
+     * migrator's one.
+     */
+    pub lock_timeout: Option<Duration>,
+
+    /**
+     * `statement_timeout` while applying this change set. Overrides the
+     * migrator's one.
+     */
+    pub statement_timeout: Option<Duration>,
+
+    /**
+     * Run statements one by one outside of a transaction, for statements
+     * like `CREATE INDEX CONCURRENTLY`. If a statement fails, the change set
+     * is left as failed in history.
+     */
+    pub no_transaction: bool,
+
+    /**
+     * The change set cannot be rolled back even if it has down SQL
+     */
+    pub irreversible: bool,
+}
+
+impl ChangeSetOptions {
+    /**
+     * Parse directive comments in SQL.
+     */
+    pub fn parse(sql: &str) -> Result<ChangeSetOptions, MigrationError> {
+        let mut options = ChangeSetOptions::default();
+        for cap in DIRECTIVE.captures_iter(sql) {
+            let key = cap.get(1).unwrap().as_str();
+            let value = cap.get(2).map(|x| x.as_str().trim());
+            match (key, value) {
+                ("lock_timeout", Some(x)) => options.lock_timeout = Some(parse_duration(x)?),
+                ("statement_timeout", Some(x)) => {
+                    options.statement_timeout = Some(parse_duration(x)?)
+                }
+                ("no_transaction", None) => options.no_transaction = true,
+                ("irreversible", None) => options.irreversible = true,
+                _ => {
+                    return Err(MigrationError::DirectiveError {
+                        path: None,
+                        message: cap[0].trim().to_string(),
+                    })
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+/**
+ * Parse a duration like PostgreSQL settings. A number without unit is
+ * milliseconds.
+ */
+pub(crate) fn parse_duration(value: &str) -> Result<Duration, MigrationError> {
+    let invalid = || MigrationError::DirectiveError {
+        path: None,
+        message: format!("invalid duration: {}", value),
+    };
+    let cap = DURATION.captures(value).ok_or_else(invalid)?;
+    let number: u64 = cap[1].parse().map_err(|_| invalid())?;
+    let seconds = |unit: u64| {
+        number
+            .checked_mul(unit)
+            .map(Duration::from_secs)
+            .ok_or_else(invalid)
+    };
+    match cap.get(2).map(|x| x.as_str()).unwrap_or("ms") {
+        "us" => Ok(Duration::from_micros(number)),
+        "ms" => Ok(Duration::from_millis(number)),
+        "s" => Ok(Duration::from_secs(number)),
+        "min" => seconds(60),
+        "h" => seconds(60 * 60),
+        _ => seconds(60 * 60 * 24),
+    }
 }
 
 /**
@@ -246,8 +676,11 @@ enum ParsedName {
 }
 
 lazy_static! {
-    static ref UP_NAME: Regex = Regex::new(r"^(\d+)__(.+)/up.sql$").unwrap();
-    static ref DOWN_NAME: Regex = Regex::new(r"^(\d+)__(.*)/down.sql$").unwrap();
+    static ref DIRECTIVE: Regex =
+        Regex::new(r"(?m)^\s*--\s*asyncmigrate:\s*(\w+)\s*(?:=\s*(.*?))?\s*$").unwrap();
+    static ref DURATION: Regex = Regex::new(r"^(\d+)\s*(us|ms|s|min|h|d)?$").unwrap();
+    static ref UP_NAME: Regex = Regex::new(r"^(\d+)__(.+)(/|__)up\.sql$").unwrap();
+    static ref DOWN_NAME: Regex = Regex::new(r"^(\d+)__(.+)(/|__)down\.sql$").unwrap();
 }
 
 impl ParsedName {
@@ -276,7 +709,11 @@ impl ParsedName {
 mod tests {
     use super::*;
 
-    fn expected_change_set() -> MigrationChangeSets {
+    /**
+     * Change sets in `schema` loaded from files relative to `base_path`
+     */
+    fn expected_change_set(base_path: &str) -> MigrationChangeSets {
+        let path = |x: &str| Some(Path::new(base_path).join(x));
         MigrationChangeSets {
             group_name: "generic".to_string(),
             change_sets: vec![
@@ -284,6 +721,9 @@ mod tests {
                     name: ChangeSetVersionName::new(1, "setup"),
                     up_sql: include_str!("../schema/001__setup/up.sql").to_string(),
                     down_sql: Some(include_str!("../schema/001__setup/down.sql").to_string()),
+                    options: ChangeSetOptions::default(),
+                    applied: None,
+                    path: path("001__setup/up.sql"),
                 },
                 ChangeSet {
                     name: ChangeSetVersionName::new(10, "minor_change"),
@@ -291,11 +731,19 @@ mod tests {
                     down_sql: Some(
                         include_str!("../schema/010__minor_change/down.sql").to_string(),
                     ),
+                    options: ChangeSetOptions::default(),
+                    applied: None,
+                    path: path("010__minor_change/up.sql"),
                 },
                 ChangeSet {
                     name: ChangeSetVersionName::new(11, "patch_change"),
                     up_sql: include_str!("../schema/011__patch_change/up.sql").to_string(),
-                    down_sql: None,
+                    down_sql: Some(
+                        include_str!("../schema/011__patch_change/down.sql").to_string(),
+                    ),
+                    options: ChangeSetOptions::default(),
+                    applied: None,
+                    path: path("011__patch_change/up.sql"),
                 },
                 ChangeSet {
                     name: ChangeSetVersionName::new(200, "major_change"),
@@ -303,6 +751,9 @@ mod tests {
                     down_sql: Some(
                         include_str!("../schema/200__major_change/down.sql").to_string(),
                     ),
+                    options: ChangeSetOptions::default(),
+                    applied: None,
+                    path: path("200__major_change/up.sql"),
                 },
             ],
         }
@@ -315,13 +766,13 @@ mod tests {
     #[test]
     fn test_load_assets() {
         let change_sets = MigrationChangeSets::load_asset("generic", Assets).unwrap();
-        assert_eq!(change_sets, expected_change_set());
+        assert_eq!(change_sets, expected_change_set(""));
     }
 
     #[test]
     fn test_load_change_set() {
         let change_sets = MigrationChangeSets::load_dir("generic", "./schema").unwrap();
-        assert_eq!(change_sets, expected_change_set());
+        assert_eq!(change_sets, expected_change_set("./schema"));
     }
 
     #[test]
@@ -364,4 +815,178 @@ mod tests {
             },)
         );
     }
+
+    #[test]
+    fn test_parse_options() {
+        let options = ChangeSetOptions::parse(
+            "-- asyncmigrate: lock_timeout = 5s\n\
+             --asyncmigrate:statement_timeout=1500\n\
+             ALTER TABLE base_table ADD COLUMN note TEXT;",
+        )
+        .unwrap();
+        assert_eq!(
+            options,
+            ChangeSetOptions {
+                lock_timeout: Some(Duration::from_secs(5)),
+                statement_timeout: Some(Duration::from_millis(1500)),
+                no_transaction: false,
+                irreversible: false,
+            }
+        );
+        assert!(
+            ChangeSetOptions::parse(
+                "-- asyncmigrate: no_transaction
+CREATE INDEX CONCURRENTLY"
+            )
+            .unwrap()
+            .no_transaction
+        );
+        assert!(
+            ChangeSetOptions::parse("-- asyncmigrate: irreversible\nDROP TABLE old_table;")
+                .unwrap()
+                .irreversible
+        );
+        assert_eq!(
+            ChangeSetOptions::parse("SELECT 1; -- asyncmigrate: lock_timeout = 5s").unwrap(),
+            ChangeSetOptions::default()
+        );
+        ChangeSetOptions::parse("-- asyncmigrate: lock_timeout = soon").unwrap_err();
+        match ChangeSetOptions::parse("-- asyncmigrate: lock_timeout = 999999999999999999d") {
+            Err(MigrationError::DirectiveError { message, .. }) => {
+                assert_eq!(message, "invalid duration: 999999999999999999d")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        ChangeSetOptions::parse("-- asyncmigrate: statement_timeout = 99999999999999999999")
+            .unwrap_err();
+        ChangeSetOptions::parse("-- asyncmigrate: unknown = 1").unwrap_err();
+    }
+
+    #[test]
+    fn test_rollback_targets() {
+        let mut change_sets = expected_change_set("");
+        change_sets.change_sets[2].down_sql = None;
+        let targets = change_sets.rollback_targets(Some(2)).unwrap();
+        assert_eq!(
+            targets.iter().map(|x| x.name.version).collect::<Vec<_>>(),
+            vec![200, 11]
+        );
+        match check_reversible("generic", &targets) {
+            Err(MigrationError::IrreversibleError { versions, .. }) => {
+                assert_eq!(versions, vec![11])
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        check_reversible("generic", &targets[..1]).unwrap();
+        assert_eq!(change_sets.rollback_targets(None).unwrap().len(), 4);
+        change_sets.rollback_targets(Some(5)).unwrap_err();
+
+        let mut marked = change_sets.change_sets[3].clone();
+        marked.options.irreversible = true;
+        assert!(!marked.is_reversible());
+    }
+
+    #[test]
+    fn test_resolve_down_sql() {
+        let stored = expected_change_set("");
+        let mut local = expected_change_set("");
+        local.change_sets[3].down_sql = Some("DROP TABLE IF EXISTS new_table;".to_string());
+        local.change_sets[2].down_sql = None;
+        local.change_sets[1].down_sql = None;
+        let down_sql = |source| {
+            local
+                .resolve_down_sql(stored.rollback_targets(None).unwrap(), source)
+                .unwrap()
+                .into_iter()
+                .map(|x| x.down_sql)
+                .collect::<Vec<_>>()
+        };
+        let fixed = Some("DROP TABLE IF EXISTS new_table;".to_string());
+        assert_eq!(
+            down_sql(RollbackSource::Stored),
+            vec![
+                stored.change_sets[3].down_sql.clone(),
+                stored.change_sets[2].down_sql.clone(),
+                stored.change_sets[1].down_sql.clone(),
+                stored.change_sets[0].down_sql.clone(),
+            ]
+        );
+        assert_eq!(
+            down_sql(RollbackSource::Local),
+            vec![
+                fixed.clone(),
+                None,
+                None,
+                stored.change_sets[0].down_sql.clone()
+            ]
+        );
+        assert_eq!(
+            down_sql(RollbackSource::PreferLocal),
+            vec![
+                fixed,
+                stored.change_sets[2].down_sql.clone(),
+                stored.change_sets[1].down_sql.clone(),
+                stored.change_sets[0].down_sql.clone(),
+            ]
+        );
+
+        local.change_sets[3].name.name = "renamed".to_string();
+        local
+            .resolve_down_sql(
+                stored.rollback_targets(None).unwrap(),
+                RollbackSource::Local,
+            )
+            .unwrap_err();
+        let missing = stored.subset(..3);
+        missing
+            .resolve_down_sql(
+                stored.rollback_targets(None).unwrap(),
+                RollbackSource::Local,
+            )
+            .unwrap_err();
+        assert_eq!(
+            missing
+                .resolve_down_sql(
+                    stored.rollback_targets(None).unwrap(),
+                    RollbackSource::PreferLocal
+                )
+                .unwrap(),
+            stored.rollback_targets(None).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_redo_plan() {
+        let applied = expected_change_set("");
+        let mut local = expected_change_set("");
+        local.change_sets[3].up_sql = "CREATE TABLE new_table(id BIGINT PRIMARY KEY);".to_string();
+        let (revert, reapply) = local
+            .redo_plan(&applied, 2, RollbackSource::Stored, SqlComparison::Exact)
+            .unwrap();
+        assert_eq!(revert, applied.rollback_targets(Some(2)).unwrap());
+        assert_eq!(reapply, local.change_sets[2..].to_vec());
+
+        local
+            .subset(..3)
+            .redo_plan(
+                &applied,
+                2,
+                RollbackSource::PreferLocal,
+                SqlComparison::Exact,
+            )
+            .unwrap_err();
+        local.change_sets[2].name.name = "renamed".to_string();
+        local
+            .redo_plan(&applied, 2, RollbackSource::Stored, SqlComparison::Exact)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_checksum() {
+        let change_sets = expected_change_set("");
+        assert_eq!(
+            change_sets.change_sets[3].checksum(),
+            "f2432c7205325025d86e5ac5c2eeabccbb0bbd8ee992ca402fc706f416bc9dee"
+        );
+    }
 }
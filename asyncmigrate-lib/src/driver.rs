@@ -1,5 +1,11 @@
+use crate::ChangeSet;
+use crate::LogEntry;
 use crate::MigrationChangeSets;
 use crate::MigrationError;
+use crate::MigrationEvent;
+use crate::Migrator;
+use crate::Observers;
+use crate::RepairAction;
 use async_trait::async_trait;
 
 #[async_trait]
@@ -7,6 +13,9 @@ pub trait Migration {
     /**
      * Run migration with change sets.
      *
+     * While migrating, the migration lock is held so that other migrators
+     * wait for this migration to finish.
+     *
      * equivalence with
      * ```ignore
      * async fn migrate(
@@ -22,14 +31,60 @@ pub trait Migration {
         count: Option<usize>,
     ) -> Result<(), MigrationError>;
 
+    /**
+     * Run migration with change sets in a single transaction.
+     *
+     * Every pending change set and its history are committed together. If
+     * one fails, the whole batch is rolled back and only the failure is
+     * recorded in the migration log. Change sets with `no_transaction`
+     * cannot be applied in this mode. The migration lock is held as in
+     * `migrate`.
+     *
+     * equivalence with
+     * ```ignore
+     * async fn migrate_atomic(
+     *    &mut self,
+     *    changesets: &MigrationChangeSets,
+     *    count: Option<usize>,
+     * ) -> Result<(), MigrationError>;
+     * ```
+     */
+    async fn migrate_atomic(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<(), MigrationError>;
+
+    /**
+     * Change sets which `migrate` would apply, in order of applying.
+     * History is checked against `changesets` as in `migrate`, but nothing
+     * is changed.
+     *
+     * equivalence with
+     * ```ignore
+     * async fn plan_migrate(
+     *    &mut self,
+     *    changesets: &MigrationChangeSets,
+     *    count: Option<usize>,
+     * ) -> Result<Vec<ChangeSet>, MigrationError>;
+     * ```
+     */
+    async fn plan_migrate(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<Vec<ChangeSet>, MigrationError>;
+
     /**
      * Update rollback SQL schema without downgrading
      *
+     * The migration lock is held as in `migrate`.
+     *
      * equivalence with
      * ```ignore
      * async fn update_rollback_sql(
-     *     &mut self,
-     *     changesets: &MigrationChangeSets,
+     *    &mut self,
+     *    changesets: &MigrationChangeSets,
      * ) -> Result<(), MigrationError>;
      * ```
      */
@@ -41,12 +96,18 @@ pub trait Migration {
     /**
      * Rollback SQL schema
      *
+     * Fails before reverting anything if a change set to revert has no down
+     * SQL or is marked as irreversible, unless irreversible rollback is
+     * allowed by `MigrationOptions::allow_irreversible`. Such change sets
+     * are then only removed from history. The migration lock is held as in
+     * `migrate`.
+     *
      * equivalence with
      * ```ignore
      * async fn rollback(
-     *     &mut self,
-     *     group_name: &str,
-     *     count: Option<usize>,
+     *    &mut self,
+     *    group_name: &str,
+     *    count: Option<usize>,
      * ) -> Result<(), MigrationError>;
      * ```
      */
@@ -56,14 +117,205 @@ pub trait Migration {
         count: Option<usize>,
     ) -> Result<(), MigrationError>;
 
+    /**
+     * Applied change sets which `rollback` would revert, in order of
+     * reverting. `ChangeSet::is_reversible` tells which of them are only
+     * removed from history. Nothing is changed.
+     *
+     * equivalence with
+     * ```ignore
+     * async fn plan_rollback(
+     *    &mut self,
+     *    group_name: &str,
+     *    count: Option<usize>,
+     * ) -> Result<Vec<ChangeSet>, MigrationError>;
+     * ```
+     */
+    async fn plan_rollback(
+        &mut self,
+        group_name: &str,
+        count: Option<usize>,
+    ) -> Result<Vec<ChangeSet>, MigrationError>;
+
+    /**
+     * Rollback SQL schema of `changesets.group_name` with down SQL taken
+     * from `changesets` or history, according to
+     * `MigrationOptions::rollback_source`.
+     *
+     * Versions and names of change sets to revert must match `changesets`.
+     * Irreversible change sets are handled as in `rollback`, and the
+     * migration lock is held as in `migrate`.
+     *
+     * equivalence with
+     * ```ignore
+     * async fn rollback_with_local(
+     *    &mut self,
+     *    changesets: &MigrationChangeSets,
+     *    count: Option<usize>,
+     * ) -> Result<(), MigrationError>;
+     * ```
+     */
+    async fn rollback_with_local(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<(), MigrationError>;
+
+    /**
+     * Applied change sets which `rollback_with_local` would revert, with
+     * the down SQL it would run. Nothing is changed.
+     *
+     * equivalence with
+     * ```ignore
+     * async fn plan_rollback_with_local(
+     *    &mut self,
+     *    changesets: &MigrationChangeSets,
+     *    count: Option<usize>,
+     * ) -> Result<Vec<ChangeSet>, MigrationError>;
+     * ```
+     */
+    async fn plan_rollback_with_local(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<Vec<ChangeSet>, MigrationError>;
+
+    /**
+     * Revert the last `count` applied change sets and apply them again
+     * from `changesets`, in a single transaction.
+     *
+     * Versions and names of the reverted change sets must match
+     * `changesets`. Down SQL is chosen as in `rollback_with_local`. If any
+     * step fails, the whole redo is rolled back and only the failure is
+     * recorded in the migration log. The migration lock is held as in
+     * `migrate`.
+     *
+     * equivalence with
+     * ```ignore
+     * async fn redo(
+     *    &mut self,
+     *    changesets: &MigrationChangeSets,
+     *    count: usize,
+     * ) -> Result<(), MigrationError>;
+     * ```
+     */
+    async fn redo(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: usize,
+    ) -> Result<(), MigrationError>;
+
+    /**
+     * Record change sets up to `up_to_version` as applied without running
+     * their SQL, to start managing a database which already has the schema.
+     *
+     * Fails if the group already has history. The migration lock is held as
+     * in `migrate`.
+     *
+     * equivalence with
+     * ```ignore
+     * async fn baseline(
+     *    &mut self,
+     *    changesets: &MigrationChangeSets,
+     *    up_to_version: i32,
+     * ) -> Result<(), MigrationError>;
+     * ```
+     */
+    async fn baseline(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        up_to_version: i32,
+    ) -> Result<(), MigrationError>;
+
+    /**
+     * Record a change set applied by hand as applied, without running its
+     * SQL. SQL stored in history is taken from `changesets`.
+     *
+     * The change set must be the next one to apply.
+     *
+     * equivalence with
+     * ```ignore
+     * async fn mark_applied(
+     *    &mut self,
+     *    changesets: &MigrationChangeSets,
+     *    version: i32,
+     * ) -> Result<(), MigrationError>;
+     * ```
+     */
+    async fn mark_applied(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        version: i32,
+    ) -> Result<(), MigrationError>;
+
+    /**
+     * Remove a change set reverted by hand from history, without running
+     * its rollback SQL.
+     *
+     * The change set must be the last applied one.
+     *
+     * equivalence with
+     * ```ignore
+     * async fn mark_reverted(
+     *    &mut self,
+     *    group_name: &str,
+     *    version: i32,
+     * ) -> Result<(), MigrationError>;
+     * ```
+     */
+    async fn mark_reverted(&mut self, group_name: &str, version: i32)
+        -> Result<(), MigrationError>;
+
+    /**
+     * Compare history with local change sets, and list changes `repair`
+     * would make. If `versions` is given, only those versions are checked.
+     *
+     * equivalence with
+     * ```ignore
+     * async fn plan_repair(
+     *    &mut self,
+     *    changesets: &MigrationChangeSets,
+     *    versions: Option<&[i32]>,
+     * ) -> Result<Vec<RepairAction>, MigrationError>;
+     * ```
+     */
+    async fn plan_repair(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        versions: Option<&[i32]>,
+    ) -> Result<Vec<RepairAction>, MigrationError>;
+
+    /**
+     * Reconcile history with local change sets without running any SQL.
+     *
+     * Name, SQL and checksum of applied change sets are replaced with local
+     * ones, and change sets not found in local files are removed from
+     * history. If `versions` is given, only those versions are repaired.
+     * Returns the changes made. The migration lock is held as in `migrate`.
+     *
+     * equivalence with
+     * ```ignore
+     * async fn repair(
+     *    &mut self,
+     *    changesets: &MigrationChangeSets,
+     *    versions: Option<&[i32]>,
+     * ) -> Result<Vec<RepairAction>, MigrationError>;
+     * ```
+     */
+    async fn repair(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        versions: Option<&[i32]>,
+    ) -> Result<Vec<RepairAction>, MigrationError>;
+
     /**
      * Load applied change sets from database.
      *
      * equivalence with
      * ```ignore
      * async fn load_applied_change_sets(
-     *     &mut self,
-     *     group_name: &str,
+     *    &mut self,
+     *    group_name: &str,
      * ) -> Result<MigrationChangeSets, MigrationError>;
      * ```
      */
@@ -71,6 +323,29 @@ pub trait Migration {
         &mut self,
         group_name: &str,
     ) -> Result<MigrationChangeSets, MigrationError>;
+
+    /**
+     * Load the migration log, newest first.
+     *
+     * If `group_name` is `None`, entries of all groups are loaded. Skip
+     * `offset` entries and load at most `limit` entries.
+     *
+     * equivalence with
+     * ```ignore
+     * async fn load_log(
+     *    &mut self,
+     *    group_name: Option<&str>,
+     *    offset: usize,
+     *    limit: usize,
+     * ) -> Result<Vec<LogEntry>, MigrationError>;
+     * ```
+     */
+    async fn load_log(
+        &mut self,
+        group_name: Option<&str>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<LogEntry>, MigrationError>;
 }
 
 /**
@@ -84,7 +359,8 @@ pub enum Connection {
     TokioPostgres(tokio_postgres::Client),
 
     /**
-     * Async MySQL connection (not implemented)
+     * Async MySQL connection. Migration is not implemented, and every
+     * method fails with `MigrationError::UnsupportedBackendError`.
      */
     #[cfg(feature = "async-mysql")]
     MySQL(mysql_async::Conn),
@@ -92,21 +368,57 @@ pub enum Connection {
 
 /**
  * Connect to a database with database URL
+ *
+ * For PostgreSQL, `url` accepts anything libpq accepts: a `postgres://` or
+ * `postgresql://` URL, or a key-value connection string like
+ * `host=/var/run/postgresql dbname=app`. Missing parameters are taken from
+ * `PG*` environment variables and the password file (`~/.pgpass`).
+ * An empty string connects with environment variables only.
  */
 pub async fn connect(url: &str) -> Result<Connection, MigrationError> {
-    if url.starts_with("postgres://") {
-        let (client, connection) = tokio_postgres::connect(url, tokio_postgres::NoTls).await?;
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                eprintln!("connection error: {}", e);
-            }
-        });
-        Ok(Connection::TokioPostgres(client))
+    connect_with_observers(url, Observers::default()).await
+}
+
+/**
+ * Connect to a database with database URL, and report a connection closed
+ * by an error to `observers` as [`MigrationEvent::ConnectionError`].
+ */
+pub async fn connect_with_observers(
+    url: &str,
+    observers: Observers,
+) -> Result<Connection, MigrationError> {
+    if url.starts_with("mysql://") {
+        Err(MigrationError::UnsupportedBackendError("MySQL"))
     } else {
-        Err(MigrationError::OtherError("unknown database protocol").into())
+        Ok(Connection::TokioPostgres(
+            connect_postgres(url, observers).await?,
+        ))
     }
 }
 
+/**
+ * Connect to PostgreSQL with a URL or a key-value connection string.
+ */
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "connect", skip_all, err)
+)]
+pub(crate) async fn connect_postgres(
+    url: &str,
+    observers: Observers,
+) -> Result<tokio_postgres::Client, MigrationError> {
+    let config = crate::pgconfig::load_config(url, &observers)?;
+    let (client, connection) = config.connect(tokio_postgres::NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            observers.emit(MigrationEvent::ConnectionError {
+                error: e.to_string(),
+            });
+        }
+    });
+    Ok(client)
+}
+
 #[async_trait]
 impl Migration for Connection {
     async fn migrate(
@@ -118,7 +430,31 @@ impl Migration for Connection {
             #[cfg(feature = "async-postgres")]
             Connection::TokioPostgres(c) => c.migrate(change_sets, count).await,
             #[cfg(feature = "async-mysql")]
-            Connection::MySQL(c) => unimplemented!(),
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn migrate_atomic(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<(), MigrationError> {
+        match self {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => c.migrate_atomic(changesets, count).await,
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn plan_migrate(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<Vec<ChangeSet>, MigrationError> {
+        match self {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => c.plan_migrate(changesets, count).await,
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
         }
     }
     async fn update_rollback_sql(
@@ -129,7 +465,7 @@ impl Migration for Connection {
             #[cfg(feature = "async-postgres")]
             Connection::TokioPostgres(c) => c.update_rollback_sql(changesets).await,
             #[cfg(feature = "async-mysql")]
-            Connection::MySQL(c) => unimplemented!(),
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
         }
     }
 
@@ -142,7 +478,115 @@ impl Migration for Connection {
             #[cfg(feature = "async-postgres")]
             Connection::TokioPostgres(c) => c.rollback(group_name, count).await,
             #[cfg(feature = "async-mysql")]
-            Connection::MySQL(c) => unimplemented!(),
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn plan_rollback(
+        &mut self,
+        group_name: &str,
+        count: Option<usize>,
+    ) -> Result<Vec<ChangeSet>, MigrationError> {
+        match self {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => c.plan_rollback(group_name, count).await,
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn rollback_with_local(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<(), MigrationError> {
+        match self {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => c.rollback_with_local(changesets, count).await,
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn plan_rollback_with_local(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<Vec<ChangeSet>, MigrationError> {
+        match self {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => c.plan_rollback_with_local(changesets, count).await,
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn redo(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: usize,
+    ) -> Result<(), MigrationError> {
+        match self {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => c.redo(changesets, count).await,
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn baseline(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        up_to_version: i32,
+    ) -> Result<(), MigrationError> {
+        match self {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => c.baseline(changesets, up_to_version).await,
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn mark_applied(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        version: i32,
+    ) -> Result<(), MigrationError> {
+        match self {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => c.mark_applied(changesets, version).await,
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn mark_reverted(
+        &mut self,
+        group_name: &str,
+        version: i32,
+    ) -> Result<(), MigrationError> {
+        match self {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => c.mark_reverted(group_name, version).await,
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn plan_repair(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        versions: Option<&[i32]>,
+    ) -> Result<Vec<RepairAction>, MigrationError> {
+        match self {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => c.plan_repair(changesets, versions).await,
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn repair(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        versions: Option<&[i32]>,
+    ) -> Result<Vec<RepairAction>, MigrationError> {
+        match self {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => c.repair(changesets, versions).await,
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
         }
     }
     async fn load_applied_change_sets(
@@ -153,7 +597,273 @@ impl Migration for Connection {
             #[cfg(feature = "async-postgres")]
             Connection::TokioPostgres(c) => c.load_applied_change_sets(group_name).await,
             #[cfg(feature = "async-mysql")]
-            Connection::MySQL(c) => unimplemented!(),
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn load_log(
+        &mut self,
+        group_name: Option<&str>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<LogEntry>, MigrationError> {
+        match self {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => c.load_log(group_name, offset, limit).await,
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+}
+
+#[async_trait]
+impl Migration for Migrator<Connection> {
+    async fn migrate(
+        &mut self,
+        change_sets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<(), MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::migrate_postgres(c, &self.options, change_sets, count).await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn migrate_atomic(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<(), MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::migrate_atomic_postgres(c, &self.options, changesets, count)
+                    .await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn plan_migrate(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<Vec<ChangeSet>, MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::plan_migrate_postgres(c, &self.options, changesets, count)
+                    .await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn update_rollback_sql(
+        &mut self,
+        changesets: &MigrationChangeSets,
+    ) -> Result<(), MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::update_rollback_sql_postgres(c, &self.options, changesets)
+                    .await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+
+    async fn rollback(
+        &mut self,
+        group_name: &str,
+        count: Option<usize>,
+    ) -> Result<(), MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::rollback_postgres(c, &self.options, group_name, count).await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn plan_rollback(
+        &mut self,
+        group_name: &str,
+        count: Option<usize>,
+    ) -> Result<Vec<ChangeSet>, MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::plan_rollback_postgres(c, &self.options, group_name, count)
+                    .await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn rollback_with_local(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<(), MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::rollback_with_local_postgres(
+                    c,
+                    &self.options,
+                    changesets,
+                    count,
+                )
+                .await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn plan_rollback_with_local(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<Vec<ChangeSet>, MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::plan_rollback_with_local_postgres(
+                    c,
+                    &self.options,
+                    changesets,
+                    count,
+                )
+                .await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn redo(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: usize,
+    ) -> Result<(), MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::redo_postgres(c, &self.options, changesets, count).await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn baseline(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        up_to_version: i32,
+    ) -> Result<(), MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::baseline_postgres(
+                    c,
+                    &self.options,
+                    changesets,
+                    up_to_version,
+                )
+                .await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn mark_applied(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        version: i32,
+    ) -> Result<(), MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::mark_applied_postgres(c, &self.options, changesets, version)
+                    .await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn mark_reverted(
+        &mut self,
+        group_name: &str,
+        version: i32,
+    ) -> Result<(), MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::mark_reverted_postgres(c, &self.options, group_name, version)
+                    .await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn plan_repair(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        versions: Option<&[i32]>,
+    ) -> Result<Vec<RepairAction>, MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::plan_repair_postgres(c, &self.options, changesets, versions)
+                    .await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn repair(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        versions: Option<&[i32]>,
+    ) -> Result<Vec<RepairAction>, MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::repair_postgres(c, &self.options, changesets, versions).await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn load_applied_change_sets(
+        &mut self,
+        group_name: &str,
+    ) -> Result<MigrationChangeSets, MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::load_migration_set(c, &self.options, group_name).await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
+        }
+    }
+    async fn load_log(
+        &mut self,
+        group_name: Option<&str>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<LogEntry>, MigrationError> {
+        match &mut self.client {
+            #[cfg(feature = "async-postgres")]
+            Connection::TokioPostgres(c) => {
+                crate::tokio_postgres::load_log(c, &self.options, group_name, offset, limit).await
+            }
+            #[cfg(feature = "async-mysql")]
+            Connection::MySQL(_) => Err(MigrationError::UnsupportedBackendError("MySQL")),
         }
     }
 }
@@ -1,7 +1,14 @@
+use std::fmt;
+use std::path::PathBuf;
 use thiserror::Error;
 
+use crate::{ApplyStatus, ChangeSetVersionName, HistoryOperation, HookEvent};
+
 /**
  * Data migration error
+ *
+ * Use [`MigrationError::code`] to match on the kind of an error without
+ * parsing its message.
  */
 #[derive(Debug, Error)]
 pub enum MigrationError {
@@ -17,10 +24,567 @@ pub enum MigrationError {
   ParseFloatError(#[from] std::num::ParseFloatError),
   #[error(transparent)]
   PostgresError(#[from] tokio_postgres::Error),
-  #[error("{0}: V{1}")]
-  InconsistentMigrationError(&'static str, i32),
-  #[error("Version mismatch: local version: {0} database version: {1}")]
-  VersionMismatchError(i32, i32),
-  #[error("Error: {0}")]
-  OtherError(&'static str),
+  #[error(
+    "V{version} in {group_name}{} failed: {}",
+    format_location(.path, .failure),
+    .failure.message
+  )]
+  StatementError {
+    group_name: String,
+    version: i32,
+    /** Local file of the change set, if known */
+    path: Option<PathBuf>,
+    failure: Box<StatementFailure>,
+    #[source]
+    source: tokio_postgres::Error,
+  },
+  #[error("{}: {source}", .path.display())]
+  FileError {
+    path: PathBuf,
+    #[source]
+    source: std::io::Error,
+  },
+  #[error("Version mismatch in {group_name}: local version: V{local} database version: V{stored}")]
+  VersionMismatchError {
+    group_name: String,
+    local: i32,
+    stored: i32,
+  },
+  #[error(
+    "{kind} of V{version} in {group_name}{} differs from history\n{diff}",
+    format_path(.path)
+  )]
+  ChangeSetMismatchError {
+    group_name: String,
+    version: i32,
+    kind: MismatchKind,
+    /** Local file of the change set, if known */
+    path: Option<PathBuf>,
+    /** Lines of history prefixed with `-`, and local ones with `+` */
+    diff: String,
+  },
+  #[error("{name} in {group_name} is applied, but not found in local files")]
+  MissingChangeSetError {
+    group_name: String,
+    name: ChangeSetVersionName,
+  },
+  #[error(
+    "Only the next change set can be marked as applied: V{version} in {group_name} (next: {})",
+    format_name(.next)
+  )]
+  NotNextChangeSetError {
+    group_name: String,
+    version: i32,
+    next: Option<ChangeSetVersionName>,
+  },
+  #[error(
+    "Only the last applied change set can be marked as reverted: V{version} in {group_name} (last: {})",
+    format_name(.last)
+  )]
+  NotLastChangeSetError {
+    group_name: String,
+    version: i32,
+    last: Option<ChangeSetVersionName>,
+  },
+  #[error("No change sets up to V{up_to_version} to baseline in {group_name}")]
+  NothingToBaselineError {
+    group_name: String,
+    up_to_version: i32,
+  },
+  #[error("Cannot revert {requested} change sets of {group_name}; only {applied} are applied")]
+  NothingToRevertError {
+    group_name: String,
+    requested: usize,
+    applied: usize,
+  },
+  #[error("Cannot baseline {group_name} which already has history")]
+  HistoryExistsError { group_name: String },
+  #[error(
+    "V{version} in {group_name}{} is no_transaction and cannot be applied atomically",
+    format_path(.path)
+  )]
+  NoTransactionError {
+    group_name: String,
+    version: i32,
+    path: Option<PathBuf>,
+  },
+  #[error("Another migrator holds the lock of {table}; gave up after waiting {waited:?}")]
+  LockTimeoutError {
+    table: String,
+    waited: std::time::Duration,
+  },
+  #[error("History table format version {found} is newer than supported version {supported}")]
+  HistoryFormatError { found: i32, supported: i32 },
+  #[error("Invalid row in {table}: {message}")]
+  InvalidHistoryError { table: String, message: String },
+  #[error("V{version} in {group_name} is {status}; fix the database, then run repair or mark-reverted")]
+  DirtyError {
+    group_name: String,
+    version: i32,
+    status: ApplyStatus,
+  },
+  #[error("Cannot roll back {} in {group_name}: no down SQL or marked irreversible", format_versions(.versions))]
+  IrreversibleError {
+    group_name: String,
+    versions: Vec<i32>,
+  },
+  #[error("Invalid directive{}: {message}", format_path(.path))]
+  DirectiveError {
+    path: Option<PathBuf>,
+    message: String,
+  },
+  #[error("Failed to migrate {} of {total} tenants: {}", .failed.len(), .failed.join(", "))]
+  TenantError { failed: Vec<String>, total: usize },
+  #[error("Pending change sets of {group_name} on {target} differ from {expected_target}: {}", format_plan_mismatch(.expected, .found))]
+  PlanMismatchError {
+    group_name: String,
+    expected_target: String,
+    expected: Vec<ChangeSetVersionName>,
+    target: String,
+    found: Vec<ChangeSetVersionName>,
+  },
+  #[error(
+    "Repair of {group_name} changed after it was planned: {} instead of {}; plan it again",
+    format_versions(.found),
+    format_versions(.expected)
+  )]
+  RepairMismatchError {
+    group_name: String,
+    expected: Vec<i32>,
+    found: Vec<i32>,
+  },
+  #[error("Failed to migrate {} of {total} databases: {}", .failed.len(), .failed.join(", "))]
+  TargetError { failed: Vec<String>, total: usize },
+  #[error("{0} is not supported yet")]
+  UnsupportedBackendError(&'static str),
+  #[error("Unknown group name: {0}")]
+  UnknownGroupError(String),
+  #[error("Invalid {kind}: {value}")]
+  InvalidValueError { kind: &'static str, value: String },
+  #[error("{context}: {source}")]
+  ContextError {
+    context: String,
+    #[source]
+    source: Box<dyn std::error::Error + Send + Sync + 'static>,
+  },
+  #[error("{event} hook refused: {message}")]
+  HookRefusedError { event: HookEvent, message: String },
+  #[error("Injected {operation} failure of V{version} in {group_name}")]
+  InjectedFailureError {
+    group_name: String,
+    version: i32,
+    operation: HistoryOperation,
+  },
+}
+
+impl MigrationError {
+  /**
+   * Stable code of this error
+   */
+  pub fn code(&self) -> ErrorCode {
+    match self {
+      MigrationError::IoError(_) | MigrationError::FileError { .. } => ErrorCode::Io,
+      MigrationError::FormatError(_)
+      | MigrationError::Utf8Error(_)
+      | MigrationError::ParseIntError(_)
+      | MigrationError::ParseFloatError(_) => ErrorCode::Parse,
+      MigrationError::PostgresError(_) | MigrationError::StatementError { .. } => ErrorCode::Database,
+      MigrationError::VersionMismatchError { .. } => ErrorCode::VersionMismatch,
+      MigrationError::ChangeSetMismatchError { kind, .. } => match kind {
+        MismatchKind::Name => ErrorCode::NameMismatch,
+        MismatchKind::UpSql => ErrorCode::UpSqlMismatch,
+        MismatchKind::DownSql => ErrorCode::DownSqlMismatch,
+      },
+      MigrationError::MissingChangeSetError { .. } => ErrorCode::MissingChangeSet,
+      MigrationError::NotNextChangeSetError { .. } => ErrorCode::NotNextChangeSet,
+      MigrationError::NotLastChangeSetError { .. } => ErrorCode::NotLastChangeSet,
+      MigrationError::NothingToBaselineError { .. } => ErrorCode::NothingToBaseline,
+      MigrationError::NothingToRevertError { .. } => ErrorCode::NothingToRevert,
+      MigrationError::HistoryExistsError { .. } => ErrorCode::HistoryExists,
+      MigrationError::NoTransactionError { .. } => ErrorCode::NoTransaction,
+      MigrationError::LockTimeoutError { .. } => ErrorCode::LockTimeout,
+      MigrationError::HistoryFormatError { .. } => ErrorCode::HistoryFormat,
+      MigrationError::InvalidHistoryError { .. } => ErrorCode::InvalidHistory,
+      MigrationError::DirtyError { .. } => ErrorCode::Dirty,
+      MigrationError::IrreversibleError { .. } => ErrorCode::Irreversible,
+      MigrationError::DirectiveError { .. } => ErrorCode::Directive,
+      MigrationError::TenantError { .. } => ErrorCode::TenantFailed,
+      MigrationError::PlanMismatchError { .. } => ErrorCode::PlanMismatch,
+      MigrationError::RepairMismatchError { .. } => ErrorCode::RepairMismatch,
+      MigrationError::TargetError { .. } => ErrorCode::TargetFailed,
+      MigrationError::UnsupportedBackendError(_) => ErrorCode::UnsupportedBackend,
+      MigrationError::UnknownGroupError(_) => ErrorCode::UnknownGroup,
+      MigrationError::InvalidValueError { .. } => ErrorCode::InvalidValue,
+      MigrationError::ContextError { source, .. } => source
+        .downcast_ref::<MigrationError>()
+        .map(|x| x.code())
+        .unwrap_or(ErrorCode::Other),
+      MigrationError::HookRefusedError { .. } => ErrorCode::HookRefused,
+      MigrationError::InjectedFailureError { .. } => ErrorCode::InjectedFailure,
+    }
+  }
+
+  /**
+   * Wrap `source` with a description of what failed.
+   */
+  pub fn context<E>(context: &str, source: E) -> MigrationError
+  where
+    E: std::error::Error + Send + Sync + 'static,
+  {
+    MigrationError::ContextError {
+      context: context.to_string(),
+      source: Box::new(source),
+    }
+  }
+}
+
+/**
+ * Kind of [`MigrationError`] which does not change between versions
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCode {
+  /** Reading a file failed */
+  Io,
+  /** Parsing a value failed */
+  Parse,
+  /** The database returned an error */
+  Database,
+  /** Local and applied change sets have different versions */
+  VersionMismatch,
+  /** Local and applied change sets have different names */
+  NameMismatch,
+  /** Local up SQL differs from applied one */
+  UpSqlMismatch,
+  /** Local down SQL differs from applied one */
+  DownSqlMismatch,
+  /** An applied change set is not found in local files */
+  MissingChangeSet,
+  /** The change set to mark as applied is not the next one */
+  NotNextChangeSet,
+  /** The change set to mark as reverted is not the last one */
+  NotLastChangeSet,
+  /** No change sets to baseline */
+  NothingToBaseline,
+  /** Fewer change sets than requested are applied */
+  NothingToRevert,
+  /** The group to baseline already has history */
+  HistoryExists,
+  /** A `no_transaction` change set in an atomic batch */
+  NoTransaction,
+  /** Another migrator holds the migration lock */
+  LockTimeout,
+  /** The history table is newer than this version */
+  HistoryFormat,
+  /** The history table has an invalid row */
+  InvalidHistory,
+  /** A change set is in progress or failed */
+  Dirty,
+  /** A change set to roll back has no down SQL */
+  Irreversible,
+  /** A directive comment is invalid */
+  Directive,
+  /** Some of tenant schemas failed to migrate */
+  TenantFailed,
+  /** Target databases would apply different change sets */
+  PlanMismatch,
+  /** History or local change sets changed after repair was planned */
+  RepairMismatch,
+  /** Some of target databases failed to migrate */
+  TargetFailed,
+  /** The database backend is not supported */
+  UnsupportedBackend,
+  /** The group is not configured */
+  UnknownGroup,
+  /** An option or an argument has an invalid value */
+  InvalidValue,
+  /** A hook callback refused the operation */
+  HookRefused,
+  /** A failure injected by `MockConnection` */
+  InjectedFailure,
+  /** Any other error */
+  Other,
+}
+
+impl ErrorCode {
+  /**
+   * Name of the code such as `up_sql_mismatch`
+   */
+  pub fn as_str(self) -> &'static str {
+    match self {
+      ErrorCode::Io => "io",
+      ErrorCode::Parse => "parse",
+      ErrorCode::Database => "database",
+      ErrorCode::VersionMismatch => "version_mismatch",
+      ErrorCode::NameMismatch => "name_mismatch",
+      ErrorCode::UpSqlMismatch => "up_sql_mismatch",
+      ErrorCode::DownSqlMismatch => "down_sql_mismatch",
+      ErrorCode::MissingChangeSet => "missing_change_set",
+      ErrorCode::NotNextChangeSet => "not_next_change_set",
+      ErrorCode::NotLastChangeSet => "not_last_change_set",
+      ErrorCode::NothingToBaseline => "nothing_to_baseline",
+      ErrorCode::NothingToRevert => "nothing_to_revert",
+      ErrorCode::HistoryExists => "history_exists",
+      ErrorCode::NoTransaction => "no_transaction",
+      ErrorCode::LockTimeout => "lock_timeout",
+      ErrorCode::HistoryFormat => "history_format",
+      ErrorCode::InvalidHistory => "invalid_history",
+      ErrorCode::Dirty => "dirty",
+      ErrorCode::Irreversible => "irreversible",
+      ErrorCode::Directive => "directive",
+      ErrorCode::TenantFailed => "tenant_failed",
+      ErrorCode::PlanMismatch => "plan_mismatch",
+      ErrorCode::RepairMismatch => "repair_mismatch",
+      ErrorCode::TargetFailed => "target_failed",
+      ErrorCode::UnsupportedBackend => "unsupported_backend",
+      ErrorCode::UnknownGroup => "unknown_group",
+      ErrorCode::InvalidValue => "invalid_value",
+      ErrorCode::HookRefused => "hook_refused",
+      ErrorCode::InjectedFailure => "injected_failure",
+      ErrorCode::Other => "other",
+    }
+  }
+}
+
+impl fmt::Display for ErrorCode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+/**
+ * What differs between a local change set and an applied one
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MismatchKind {
+  Name,
+  UpSql,
+  DownSql,
+}
+
+impl fmt::Display for MismatchKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      MismatchKind::Name => "Name",
+      MismatchKind::UpSql => "Up SQL",
+      MismatchKind::DownSql => "Down SQL",
+    })
+  }
+}
+
+/**
+ * Lines around the first difference of `stored` and `local`, with lines of
+ * `stored` prefixed with `-` and lines of `local` with `+`.
+ */
+pub(crate) fn diff_snippet(stored: &str, local: &str) -> String {
+  const MAX_LINES: usize = 3;
+  let stored: Vec<_> = stored.lines().collect();
+  let local: Vec<_> = local.lines().collect();
+  let prefix = stored
+    .iter()
+    .zip(local.iter())
+    .take_while(|(a, b)| a == b)
+    .count();
+  let suffix = stored[prefix..]
+    .iter()
+    .rev()
+    .zip(local[prefix..].iter().rev())
+    .take_while(|(a, b)| a == b)
+    .count();
+
+  let mut snippet = format!("line {}:", prefix + 1);
+  for (mark, lines) in [("-", &stored), ("+", &local)].iter() {
+    let changed = &lines[prefix..lines.len() - suffix];
+    for line in changed.iter().take(MAX_LINES) {
+      snippet.push_str(&format!("\n{} {}", mark, line));
+    }
+    if changed.len() > MAX_LINES {
+      snippet.push_str(&format!("\n{} ...", mark));
+    }
+  }
+  snippet
+}
+
+fn format_plan_mismatch(expected: &[ChangeSetVersionName], found: &[ChangeSetVersionName]) -> String {
+  let format = |names: &[ChangeSetVersionName]| {
+    if names.is_empty() {
+      "none".to_string()
+    } else {
+      names.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", ")
+    }
+  };
+  if expected == found {
+    format!("{} with different up SQL", format(found))
+  } else {
+    format!("{} instead of {}", format(found), format(expected))
+  }
+}
+
+fn format_versions(versions: &[i32]) -> String {
+  if versions.is_empty() {
+    return "none".to_string();
+  }
+  versions
+    .iter()
+    .map(|x| format!("V{}", x))
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+fn format_path(path: &Option<PathBuf>) -> String {
+  path
+    .as_ref()
+    .map(|x| format!(" ({})", x.display()))
+    .unwrap_or_default()
+}
+
+/**
+ * A failed statement of a change set, located in its SQL by the `position`
+ * field of the Postgres error
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementFailure {
+  /** Primary error message of Postgres */
+  pub message: String,
+  /** SQLSTATE code such as `42601` */
+  pub sql_state: String,
+  pub detail: Option<String>,
+  pub hint: Option<String>,
+  /** Index of the failed statement from 0, if known */
+  pub statement_index: Option<usize>,
+  /** SQL of the failed statement, if known */
+  pub statement: Option<String>,
+  /** Position of the error in the SQL, if Postgres reported it */
+  pub location: Option<SqlLocation>,
+}
+
+/**
+ * Position in an SQL file
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlLocation {
+  /** Line from 1 */
+  pub line: usize,
+  /** Column from 1, counted in characters */
+  pub column: usize,
+  /** Text of the line without the line break */
+  pub line_text: String,
+}
+
+impl SqlLocation {
+  /**
+   * The line and a caret under the column, such as
+   *
+   * ```text
+   * CREATE TABL foo;
+   *        ^
+   * ```
+   */
+  pub fn caret(&self) -> String {
+    // keep tabs so that the caret lines up with the text
+    let padding: String = self
+      .line_text
+      .chars()
+      .take(self.column - 1)
+      .map(|x| if x == '\t' { '\t' } else { ' ' })
+      .collect();
+    format!("{}\n{}^", self.line_text, padding)
+  }
+}
+
+fn format_location(path: &Option<PathBuf>, failure: &StatementFailure) -> String {
+  match (path, &failure.location) {
+    (Some(path), Some(location)) => format!(" ({}:{}:{})", path.display(), location.line, location.column),
+    (None, Some(location)) => format!(" (line {}, column {})", location.line, location.column),
+    (path, None) => format_path(path),
+  }
+}
+
+fn format_name(name: &Option<ChangeSetVersionName>) -> String {
+  name
+    .as_ref()
+    .map(|x| x.to_string())
+    .unwrap_or_else(|| "none".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_diff_snippet() {
+    assert_eq!(
+      diff_snippet("CREATE TABLE a;\nSELECT 1;\nSELECT 2;", "CREATE TABLE a;\nSELECT 3;\nSELECT 2;"),
+      "line 2:\n- SELECT 1;\n+ SELECT 3;"
+    );
+    assert_eq!(diff_snippet("", "SELECT 1;"), "line 1:\n+ SELECT 1;");
+    assert_eq!(
+      diff_snippet("a\nb\nc\nd\ne", "x"),
+      "line 1:\n- a\n- b\n- c\n- ...\n+ x"
+    );
+  }
+
+  #[test]
+  fn test_caret() {
+    let location = SqlLocation {
+      line: 2,
+      column: 9,
+      line_text: "\tCREATE TABL foo;".to_string(),
+    };
+    assert_eq!(location.caret(), "\tCREATE TABL foo;\n\t       ^");
+  }
+
+  #[test]
+  fn test_plan_mismatch() {
+    let plan = vec![ChangeSetVersionName::new(2, "add")];
+    let error = MigrationError::PlanMismatchError {
+      group_name: "default".to_string(),
+      expected_target: "shard1".to_string(),
+      expected: plan.clone(),
+      target: "shard2".to_string(),
+      found: Vec::new(),
+    };
+    assert_eq!(error.code(), ErrorCode::PlanMismatch);
+    assert_eq!(
+      error.to_string(),
+      "Pending change sets of default on shard2 differ from shard1: none instead of V2 add"
+    );
+    let error = MigrationError::PlanMismatchError {
+      group_name: "default".to_string(),
+      expected_target: "shard1".to_string(),
+      expected: plan.clone(),
+      target: "shard2".to_string(),
+      found: plan,
+    };
+    assert_eq!(
+      error.to_string(),
+      "Pending change sets of default on shard2 differ from shard1: V2 add with different up SQL"
+    );
+  }
+
+  #[test]
+  fn test_code() {
+    let error = MigrationError::ChangeSetMismatchError {
+      group_name: "default".to_string(),
+      version: 2,
+      kind: MismatchKind::UpSql,
+      path: Some(PathBuf::from("schema/2__add__up.sql")),
+      diff: diff_snippet("SELECT 1;", "SELECT 2;"),
+    };
+    assert_eq!(error.code(), ErrorCode::UpSqlMismatch);
+    assert_eq!(
+      error.to_string(),
+      "Up SQL of V2 in default (schema/2__add__up.sql) differs from history\nline 1:\n- SELECT 1;\n+ SELECT 2;"
+    );
+    let error = MigrationError::context("Failed to load", MigrationError::UnknownGroupError("x".to_string()));
+    assert_eq!(error.code(), ErrorCode::UnknownGroup);
+    assert_eq!(error.to_string(), "Failed to load: Unknown group name: x");
+    let error = MigrationError::InjectedFailureError {
+      group_name: "default".to_string(),
+      version: 3,
+      operation: HistoryOperation::Rollback,
+    };
+    assert_eq!(error.code(), ErrorCode::InjectedFailure);
+    assert_eq!(error.to_string(), "Injected rollback failure of V3 in default");
+  }
 }
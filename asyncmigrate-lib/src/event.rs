@@ -0,0 +1,281 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{ChangeSetVersionName, HistoryOperation};
+
+/**
+ * Progress of a migration reported to observers.
+ *
+ * The library never prints. Register a [`MigrationObserver`] with
+ * `MigrationOptions::observer` to log events or to show them on a console.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationEvent {
+    /**
+     * Applying or reverting change sets of a group started
+     */
+    Started {
+        group_name: String,
+        operation: HistoryOperation,
+        change_sets: usize,
+    },
+
+    /**
+     * Applying or reverting a change set began
+     */
+    StepStarted {
+        group_name: String,
+        operation: HistoryOperation,
+        change_set: ChangeSetVersionName,
+    },
+
+    /**
+     * A change set was applied or reverted and committed
+     */
+    StepFinished {
+        group_name: String,
+        operation: HistoryOperation,
+        change_set: ChangeSetVersionName,
+        duration: Duration,
+    },
+
+    /**
+     * SQL of a change set was skipped, and only history was changed
+     */
+    Skipped {
+        group_name: String,
+        operation: HistoryOperation,
+        change_set: ChangeSetVersionName,
+        reason: &'static str,
+    },
+
+    /**
+     * History of a change set was changed without running its SQL, such as
+     * by baseline, mark-applied or repair
+     */
+    Recorded {
+        group_name: String,
+        operation: HistoryOperation,
+        change_set: ChangeSetVersionName,
+    },
+
+    /**
+     * An operation failed. `statement` is the index, from 0, of the failed
+     * statement of a `no_transaction` change set.
+     */
+    Failed {
+        group_name: String,
+        operation: HistoryOperation,
+        change_set: Option<ChangeSetVersionName>,
+        statement: Option<usize>,
+        error: String,
+    },
+
+    /**
+     * A change set failed by `lock_timeout`, and will be retried after
+     * `wait`
+     */
+    Retrying {
+        group_name: String,
+        change_set: ChangeSetVersionName,
+        attempt: u32,
+        retries: u32,
+        wait: Duration,
+    },
+
+    /**
+     * Atomic migration or redo failed, and all of its changes were rolled
+     * back
+     */
+    RolledBack { group_name: String },
+
+    /**
+     * All change sets of a group were applied or reverted
+     */
+    Finished {
+        group_name: String,
+        operation: HistoryOperation,
+        duration: Duration,
+    },
+
+    /**
+     * Another migrator holds the migration lock
+     */
+    LockWaiting,
+
+    /**
+     * The migration lock was acquired after waiting for `waited`
+     */
+    LockAcquired { waited: Duration },
+
+    /**
+     * The connection to the database was closed by an error
+     */
+    ConnectionError { error: String },
+
+    /**
+     * The password file was not used because group or others can access it
+     */
+    PasswordFileIgnored { path: PathBuf },
+
+    /**
+     * Migrating a tenant schema started
+     */
+    TenantStarted { schema: String },
+
+    /**
+     * Migrating a tenant schema finished, with the error if it failed
+     */
+    TenantFinished {
+        schema: String,
+        error: Option<String>,
+    },
+
+    /**
+     * Migrating a target database started. `target` is its URL with the
+     * password hidden.
+     */
+    TargetStarted { target: String },
+
+    /**
+     * Migrating a target database finished, with the error if it failed
+     */
+    TargetFinished {
+        target: String,
+        error: Option<String>,
+    },
+}
+
+/**
+ * Receiver of [`MigrationEvent`]s.
+ *
+ * Implemented for closures and for tokio unbounded senders, so events can
+ * be handled in place or passed to an async task.
+ *
+ * ```
+ * use asyncmigrate::{MigrationEvent, MigrationOptions};
+ *
+ * let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MigrationEvent>();
+ * let options = MigrationOptions::default()
+ *     .observer(|event: &MigrationEvent| eprintln!("{:?}", event))
+ *     .observer(sender);
+ * ```
+ */
+pub trait MigrationObserver: Send + Sync {
+    fn on_event(&self, event: &MigrationEvent);
+}
+
+impl<F> MigrationObserver for F
+where
+    F: Fn(&MigrationEvent) + Send + Sync,
+{
+    fn on_event(&self, event: &MigrationEvent) {
+        self(event)
+    }
+}
+
+impl MigrationObserver for tokio::sync::mpsc::UnboundedSender<MigrationEvent> {
+    fn on_event(&self, event: &MigrationEvent) {
+        // events after the receiver is dropped are discarded
+        let _ = self.send(event.clone());
+    }
+}
+
+/**
+ * Observers registered to a migrator
+ */
+#[derive(Clone, Default)]
+pub struct Observers {
+    observers: Vec<Arc<dyn MigrationObserver>>,
+}
+
+impl Observers {
+    /**
+     * Create an empty list of observers.
+     */
+    pub fn new() -> Self {
+        Observers::default()
+    }
+
+    /**
+     * Add an observer.
+     */
+    pub fn observer<O: MigrationObserver + 'static>(mut self, observer: O) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    /**
+     * `true` if no observer is registered.
+     */
+    pub fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    /**
+     * Deliver `event` to all observers in order of registration.
+     */
+    pub(crate) fn emit(&self, event: MigrationEvent) {
+        for one in self.observers.iter() {
+            one.on_event(&event);
+        }
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Observers")
+            .field("observers", &self.observers.len())
+            .finish()
+    }
+}
+
+impl PartialEq for Observers {
+    fn eq(&self, other: &Self) -> bool {
+        self.observers.len() == other.observers.len()
+            && self
+                .observers
+                .iter()
+                .zip(other.observers.iter())
+                .all(|(a, b)| Arc::ptr_eq(a, b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_emit() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let recorder = received.clone();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let observers = Observers::new()
+            .observer(move |event: &MigrationEvent| recorder.lock().unwrap().push(event.clone()))
+            .observer(sender);
+        assert!(!observers.is_empty());
+
+        observers.emit(MigrationEvent::LockWaiting);
+        observers.emit(MigrationEvent::RolledBack {
+            group_name: "default".to_string(),
+        });
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                MigrationEvent::LockWaiting,
+                MigrationEvent::RolledBack {
+                    group_name: "default".to_string()
+                }
+            ]
+        );
+        assert_eq!(receiver.recv().await, Some(MigrationEvent::LockWaiting));
+
+        // a closed channel does not fail the migration
+        drop(receiver);
+        observers.emit(MigrationEvent::LockWaiting);
+        assert_eq!(received.lock().unwrap().len(), 3);
+    }
+}
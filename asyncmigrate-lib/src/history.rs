@@ -0,0 +1,255 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+/**
+ * Version of asyncmigrate recorded in the history table
+ */
+pub(crate) const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/**
+ * Record of an applied change set in the history table.
+ *
+ * Each value is `None` when the change set was applied by an older version of
+ * asyncmigrate which did not record it.
+ */
+#[derive(Debug, Clone, Default, PartialEq, PartialOrd, Ord, Eq)]
+pub struct AppliedInfo {
+    /**
+     * Time when the change set was applied
+     */
+    pub applied_at: Option<SystemTime>,
+
+    /**
+     * Time taken to run the up SQL
+     */
+    pub execution_time: Option<Duration>,
+
+    /**
+     * Database user who applied the change set
+     */
+    pub applied_by: Option<String>,
+
+    /**
+     * Host name of the machine which ran the migration
+     */
+    pub hostname: Option<String>,
+
+    /**
+     * asyncmigrate version which applied the change set
+     */
+    pub tool_version: Option<String>,
+
+    /**
+     * Checksum of the up SQL at the time of applying
+     */
+    pub checksum: Option<String>,
+
+    /**
+     * Whether the change set is completely applied
+     */
+    pub status: ApplyStatus,
+
+    /**
+     * Index of the failed statement, from 0, of a failed change set
+     */
+    pub failed_statement: Option<i32>,
+
+    /**
+     * Error message of a failed change set
+     */
+    pub error: Option<String>,
+}
+
+/**
+ * State of a change set in the history table
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Ord, Eq, Hash)]
+pub enum ApplyStatus {
+    /**
+     * The change set is completely applied
+     */
+    #[default]
+    Applied,
+
+    /**
+     * The change set is being applied, or the migrator died while applying
+     * it
+     */
+    InProgress,
+
+    /**
+     * A statement of the change set failed, and the database may be left
+     * partially changed
+     */
+    Failed,
+}
+
+impl ApplyStatus {
+    /**
+     * Name of the status stored in the history table
+     */
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ApplyStatus::Applied => "applied",
+            ApplyStatus::InProgress => "in_progress",
+            ApplyStatus::Failed => "failed",
+        }
+    }
+
+    pub(crate) fn parse(value: &str) -> Option<ApplyStatus> {
+        match value {
+            "applied" => Some(ApplyStatus::Applied),
+            "in_progress" => Some(ApplyStatus::InProgress),
+            "failed" => Some(ApplyStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ApplyStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/**
+ * Operation recorded in the migration log
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HistoryOperation {
+    /**
+     * A change set was applied
+     */
+    Migrate,
+
+    /**
+     * A change set was reverted
+     */
+    Rollback,
+
+    /**
+     * Rollback SQL of a change set was updated
+     */
+    UpdateRollbackSql,
+
+    /**
+     * A change set was recorded as applied without running it
+     */
+    Baseline,
+
+    /**
+     * A change set applied by hand was recorded as applied
+     */
+    MarkApplied,
+
+    /**
+     * A change set reverted by hand was removed from history
+     */
+    MarkReverted,
+
+    /**
+     * History of a change set was reconciled with local files
+     */
+    Repair,
+}
+
+impl HistoryOperation {
+    /**
+     * Name of the operation stored in the migration log
+     */
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HistoryOperation::Migrate => "migrate",
+            HistoryOperation::Rollback => "rollback",
+            HistoryOperation::UpdateRollbackSql => "update_rollback_sql",
+            HistoryOperation::Baseline => "baseline",
+            HistoryOperation::MarkApplied => "mark_applied",
+            HistoryOperation::MarkReverted => "mark_reverted",
+            HistoryOperation::Repair => "repair",
+        }
+    }
+}
+
+impl fmt::Display for HistoryOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/**
+ * An entry of the migration log.
+ *
+ * Unlike the history table, the log is append-only. It keeps records of
+ * reverted change sets and failed operations.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    /**
+     * Sequential ID of the entry
+     */
+    pub id: i64,
+
+    /**
+     * Time when the operation finished
+     */
+    pub logged_at: SystemTime,
+
+    /**
+     * Group name of the change set
+     */
+    pub group_name: String,
+
+    /**
+     * Version of the change set
+     */
+    pub version: Option<i32>,
+
+    /**
+     * Name of the change set
+     */
+    pub name: Option<String>,
+
+    /**
+     * Operation name such as `migrate` or `rollback`
+     */
+    pub operation: String,
+
+    /**
+     * `true` if the operation succeeded
+     */
+    pub success: bool,
+
+    /**
+     * Database user who ran the operation
+     */
+    pub actor: String,
+
+    /**
+     * Host name of the machine which ran the operation
+     */
+    pub hostname: Option<String>,
+
+    /**
+     * asyncmigrate version which ran the operation
+     */
+    pub tool_version: Option<String>,
+
+    /**
+     * Error message of a failed operation
+     */
+    pub error: Option<String>,
+}
+
+/**
+ * Host name of this machine, if available.
+ */
+pub(crate) fn hostname() -> Option<String> {
+    env::var("HOSTNAME")
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+}
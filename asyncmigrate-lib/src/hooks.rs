@@ -0,0 +1,323 @@
+use rust_embed::RustEmbed;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str;
+use std::sync::Arc;
+
+use crate::{ChangeSet, MigrationError};
+
+/**
+ * Point of a migration or a rollback where hooks run
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HookEvent {
+    /**
+     * Before applying change sets
+     */
+    BeforeMigrate,
+
+    /**
+     * Before the up SQL, in the transaction of each change set
+     */
+    BeforeEachMigrate,
+
+    /**
+     * After the up SQL, in the transaction of each change set
+     */
+    AfterEachMigrate,
+
+    /**
+     * After all change sets are applied
+     */
+    AfterMigrate,
+
+    /**
+     * Before reverting change sets
+     */
+    BeforeRollback,
+
+    /**
+     * Before the down SQL, in the transaction of each change set
+     */
+    BeforeEachRollback,
+
+    /**
+     * After the down SQL, in the transaction of each change set
+     */
+    AfterEachRollback,
+
+    /**
+     * After all change sets are reverted
+     */
+    AfterRollback,
+}
+
+impl HookEvent {
+    /**
+     * All events in order of a migration and a rollback
+     */
+    pub const ALL: [HookEvent; 8] = [
+        HookEvent::BeforeMigrate,
+        HookEvent::BeforeEachMigrate,
+        HookEvent::AfterEachMigrate,
+        HookEvent::AfterMigrate,
+        HookEvent::BeforeRollback,
+        HookEvent::BeforeEachRollback,
+        HookEvent::AfterEachRollback,
+        HookEvent::AfterRollback,
+    ];
+
+    /**
+     * Name of the event such as `beforeMigrate`
+     */
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HookEvent::BeforeMigrate => "beforeMigrate",
+            HookEvent::BeforeEachMigrate => "beforeEachMigrate",
+            HookEvent::AfterEachMigrate => "afterEachMigrate",
+            HookEvent::AfterMigrate => "afterMigrate",
+            HookEvent::BeforeRollback => "beforeRollback",
+            HookEvent::BeforeEachRollback => "beforeEachRollback",
+            HookEvent::AfterEachRollback => "afterEachRollback",
+            HookEvent::AfterRollback => "afterRollback",
+        }
+    }
+
+    /**
+     * Name of the hook SQL file such as `beforeMigrate.sql`
+     */
+    pub fn file_name(self) -> String {
+        format!("{}.sql", self.as_str())
+    }
+}
+
+impl fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/**
+ * What a hook is called for
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct HookContext<'a> {
+    pub event: HookEvent,
+    pub group_name: &'a str,
+
+    /**
+     * Change set being applied or reverted. `None` for hooks which run
+     * before or after all change sets.
+     */
+    pub change_set: Option<&'a ChangeSet>,
+}
+
+/**
+ * A Rust callback called as a hook. Returning an error, such as
+ * [`MigrationError::HookRefusedError`], aborts the operation, and rolls back
+ * the transaction the hook runs in.
+ */
+pub type HookCallback = Arc<dyn Fn(&HookContext<'_>) -> Result<(), MigrationError> + Send + Sync>;
+
+/**
+ * SQL and Rust callbacks run around migrations and rollbacks.
+ *
+ * Hook SQL files are discovered by name, such as `beforeMigrate.sql` and
+ * `afterEachMigrate.sql`, in a change set directory. For each event, the SQL
+ * runs first, and then the callbacks in order of registration.
+ *
+ * Per change set hooks run in the transaction of the change set, so a failed
+ * hook rolls back the change set. Hooks before and after all change sets run
+ * outside of them, or in the transaction of the whole batch with atomic
+ * migration and redo.
+ *
+ * ```no_run
+ * use asyncmigrate::{HookEvent, Hooks, MigrationOptions};
+ * # fn run() -> Result<(), asyncmigrate::MigrationError> {
+ * let hooks = Hooks::load_dir("./schema")?
+ *     .sql(HookEvent::AfterMigrate, "ANALYZE;")
+ *     .callback(HookEvent::AfterEachMigrate, |context| {
+ *         println!("applied {}", context.change_set.unwrap().name);
+ *         Ok(())
+ *     });
+ * let options = MigrationOptions::default().hooks(hooks);
+ * # Ok(())
+ * # }
+ * ```
+ */
+#[derive(Clone, Default)]
+pub struct Hooks {
+    sql: HashMap<HookEvent, String>,
+    callbacks: Vec<(HookEvent, HookCallback)>,
+}
+
+impl Hooks {
+    /**
+     * Create hooks which do nothing.
+     */
+    pub fn new() -> Self {
+        Hooks::default()
+    }
+
+    /**
+     * Load hook SQL files in a directory.
+     */
+    pub fn load_dir<P: AsRef<Path>>(path: P) -> Result<Hooks, MigrationError> {
+        let mut hooks = Hooks::new();
+        for event in HookEvent::ALL.iter() {
+            let file_path = path.as_ref().join(event.file_name());
+            match fs::read_to_string(&file_path) {
+                Ok(sql) => hooks.sql.insert(*event, sql),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(source) => {
+                    return Err(MigrationError::FileError {
+                        path: file_path,
+                        source,
+                    })
+                }
+            };
+        }
+        Ok(hooks)
+    }
+
+    /**
+     * Load hook SQL files from a RustEmbed asset.
+     */
+    pub fn load_asset<A: RustEmbed>(_asset: A) -> Result<Hooks, MigrationError> {
+        let mut hooks = Hooks::new();
+        for event in HookEvent::ALL.iter() {
+            if let Some(data) = A::get(&event.file_name()) {
+                hooks
+                    .sql
+                    .insert(*event, str::from_utf8(data.as_ref())?.to_string());
+            }
+        }
+        Ok(hooks)
+    }
+
+    /**
+     * Set SQL of `event`, replacing SQL loaded from a file.
+     */
+    pub fn sql(mut self, event: HookEvent, sql: &str) -> Self {
+        self.sql.insert(event, sql.to_string());
+        self
+    }
+
+    /**
+     * Register a callback of `event`.
+     */
+    pub fn callback<F>(mut self, event: HookEvent, callback: F) -> Self
+    where
+        F: Fn(&HookContext<'_>) -> Result<(), MigrationError> + Send + Sync + 'static,
+    {
+        self.callbacks.push((event, Arc::new(callback)));
+        self
+    }
+
+    /**
+     * SQL of `event`
+     */
+    pub fn get_sql(&self, event: HookEvent) -> Option<&str> {
+        self.sql.get(&event).map(|x| x.as_str())
+    }
+
+    /**
+     * Call callbacks of `context.event`.
+     */
+    pub(crate) fn call(&self, context: &HookContext<'_>) -> Result<(), MigrationError> {
+        for (event, callback) in self.callbacks.iter() {
+            if *event == context.event {
+                callback(context)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sql: Vec<_> = self.sql.keys().collect();
+        sql.sort();
+        f.debug_struct("Hooks")
+            .field("sql", &sql)
+            .field("callbacks", &self.callbacks.len())
+            .finish()
+    }
+}
+
+impl PartialEq for Hooks {
+    fn eq(&self, other: &Self) -> bool {
+        self.sql == other.sql
+            && self.callbacks.len() == other.callbacks.len()
+            && self
+                .callbacks
+                .iter()
+                .zip(other.callbacks.iter())
+                .all(|(a, b)| a.0 == b.0 && Arc::ptr_eq(&a.1, &b.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_load_dir() {
+        let dir = std::env::temp_dir().join(format!("asyncmigrate-hooks-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("beforeMigrate.sql"), "SET ROLE owner;").unwrap();
+        fs::write(dir.join("afterEachMigrate.sql"), "ANALYZE;").unwrap();
+        fs::write(dir.join("1__setup__up.sql"), "SELECT 1;").unwrap();
+        let hooks = Hooks::load_dir(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            hooks.get_sql(HookEvent::BeforeMigrate),
+            Some("SET ROLE owner;")
+        );
+        assert_eq!(hooks.get_sql(HookEvent::AfterEachMigrate), Some("ANALYZE;"));
+        assert_eq!(hooks.get_sql(HookEvent::AfterRollback), None);
+    }
+
+    #[test]
+    fn test_call() {
+        let called = Arc::new(Mutex::new(Vec::new()));
+        let recorder = called.clone();
+        let hooks = Hooks::new()
+            .callback(HookEvent::AfterEachMigrate, move |context| {
+                recorder
+                    .lock()
+                    .unwrap()
+                    .push(context.group_name.to_string());
+                Ok(())
+            })
+            .callback(HookEvent::AfterMigrate, |context| {
+                Err(MigrationError::HookRefusedError {
+                    event: context.event,
+                    message: "not now".to_string(),
+                })
+            });
+
+        let context = |event| HookContext {
+            event,
+            group_name: "default",
+            change_set: None,
+        };
+        hooks.call(&context(HookEvent::AfterEachMigrate)).unwrap();
+        hooks.call(&context(HookEvent::BeforeMigrate)).unwrap();
+        assert_eq!(
+            hooks
+                .call(&context(HookEvent::AfterMigrate))
+                .unwrap_err()
+                .to_string(),
+            "afterMigrate hook refused: not now"
+        );
+        assert_eq!(*called.lock().unwrap(), vec!["default".to_string()]);
+        assert_eq!(hooks.clone(), hooks);
+        assert_ne!(hooks, Hooks::new());
+    }
+}
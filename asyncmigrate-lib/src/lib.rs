@@ -3,6 +3,11 @@
 //! ## Supported database
 //! * PostgreSQL
 //!
+//! ## Optional features
+//! * `tracing`: spans for connect, history load, migration lock and each
+//!   up/down step (with group, version and name), debug events with SQL,
+//!   and error events with the Postgres error code
+//!
 //! ## License
 //! Apache License 2.0
 //!
@@ -33,14 +38,62 @@
 //! # Ok(())
 //! # }
 //! ```
+//!
+//! `Migration` is also implemented for `tokio_postgres::Client` and
+//! `tokio_postgres::Transaction`. With a transaction, each change set is
+//! applied in a savepoint, and the whole migration is committed or rolled
+//! back together with the transaction.
+//!
+//! The library does not print anything. Progress such as applied change
+//! sets, failures and waits for the migration lock is delivered as
+//! [`MigrationEvent`]s to observers added by `MigrationOptions::observer`.
+//!
+//! Errors about a change set carry its group, version and file, and
+//! [`MigrationError::code`] returns a stable [`ErrorCode`] to match on.
+//!
+//! For schema-per-tenant databases, [`TenantMigrator`] applies a group to
+//! many schemas with history kept in each schema, and reports which of them
+//! failed so that only those can be retried. For identical databases such
+//! as shards, [`FanOutMigrator`] checks that every target has the same
+//! pending change sets, then applies them concurrently.
+//!
+//! For unit tests without a database, [`mock::MockConnection`] keeps
+//! migration history in memory and records SQL instead of executing it.
+
+#[macro_use]
+mod trace;
 
 mod changeset;
 mod driver;
 mod error;
-pub use changeset::{ChangeSet, ChangeSetVersionName, MigrationChangeSets};
-pub use driver::{connect, Connection, Migration};
-pub use error::MigrationError;
+mod event;
+#[cfg(feature = "async-postgres")]
+mod fanout;
+mod history;
+mod hooks;
+mod migrator;
+#[cfg(feature = "async-postgres")]
+mod pgconfig;
+mod repair;
+mod sql;
+#[cfg(feature = "async-postgres")]
+mod tenant;
+pub use changeset::{ChangeSet, ChangeSetOptions, ChangeSetVersionName, MigrationChangeSets};
+pub use driver::{connect, connect_with_observers, Connection, Migration};
+pub use error::{ErrorCode, MigrationError, MismatchKind, SqlLocation, StatementFailure};
+pub use event::{MigrationEvent, MigrationObserver, Observers};
+#[cfg(feature = "async-postgres")]
+pub use fanout::{FanOutMigrator, FanOutReport, TargetReport, TargetStatus};
+pub use history::{AppliedInfo, ApplyStatus, HistoryOperation, LogEntry};
+pub use hooks::{HookCallback, HookContext, HookEvent, Hooks};
+pub use migrator::{
+    MigrationOptions, Migrator, RollbackSource, SqlComparison, DEFAULT_HISTORY_TABLE,
+};
+pub use repair::RepairAction;
+#[cfg(feature = "async-postgres")]
+pub use tenant::{TenantMigrator, TenantSchemas, TenantSummary};
 
+pub mod mock;
 #[cfg(feature = "async-postgres")]
 pub mod tokio_postgres;
 
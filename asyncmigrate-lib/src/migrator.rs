@@ -0,0 +1,607 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::sql::tokens;
+use crate::{
+    ChangeSet, ChangeSetVersionName, Hooks, MigrationError, MigrationObserver, Observers,
+    RepairAction,
+};
+
+/**
+ * Default name of the migration history table
+ */
+pub const DEFAULT_HISTORY_TABLE: &str = "db_migration";
+
+/**
+ * Longest wait before a retry of a change set failed by `lock_timeout`,
+ * unless `lock_retry_interval` is longer
+ */
+const MAX_LOCK_RETRY_WAIT: Duration = Duration::from_secs(30);
+
+/**
+ * Options of a migrator.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationOptions {
+    /**
+     * Schema of the history table. If `None`, the table is resolved with
+     * `search_path`. The schema is created if it does not exist.
+     */
+    pub history_schema: Option<String>,
+
+    /**
+     * Name of the history table
+     */
+    pub history_table: String,
+
+    /**
+     * Take an advisory lock during migrate, rollback and update of rollback
+     * SQL, so that only one migrator changes the database at once.
+     */
+    pub migration_lock: bool,
+
+    /**
+     * How long to wait for the migration lock held by another migrator.
+     * If `None`, wait until the lock is released.
+     */
+    pub migration_lock_timeout: Option<Duration>,
+
+    /**
+     * `lock_timeout` while applying a change set
+     */
+    pub lock_timeout: Option<Duration>,
+
+    /**
+     * `statement_timeout` while applying a change set
+     */
+    pub statement_timeout: Option<Duration>,
+
+    /**
+     * How many times to retry a change set which failed by `lock_timeout`
+     */
+    pub lock_retries: u32,
+
+    /**
+     * Wait before the first retry. The wait is doubled for each retry up
+     * to 30 seconds.
+     */
+    pub lock_retry_interval: Duration,
+
+    /**
+     * Roll back change sets without down SQL or marked as irreversible by
+     * only removing them from history. If `false`, such a rollback fails
+     * before reverting anything.
+     */
+    pub allow_irreversible: bool,
+
+    /**
+     * Where down SQL is taken from when rolling back with local change sets
+     */
+    pub rollback_source: RollbackSource,
+
+    /**
+     * How local SQL is compared with SQL stored in history
+     */
+    pub sql_comparison: SqlComparison,
+
+    /**
+     * SQL and callbacks run around migrations and rollbacks
+     */
+    pub hooks: Hooks,
+
+    /**
+     * Observers which receive progress of migrations
+     */
+    pub observers: Observers,
+
+    /**
+     * Actions `repair` must apply, such as ones returned by `plan_repair`
+     * and confirmed by a user. If history or local change sets changed
+     * since then, `repair` fails under the migration lock without changing
+     * anything.
+     */
+    pub expected_repair: Option<Vec<RepairAction>>,
+
+    /**
+     * Change sets `migrate` must apply, checked under the migration lock
+     */
+    pub(crate) expected_plan: Option<ExpectedPlan>,
+}
+
+/**
+ * Change sets planned on another target, which a migration must apply
+ * exactly.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ExpectedPlan {
+    /**
+     * Target the plan was made on
+     */
+    pub(crate) planned_on: String,
+
+    /**
+     * Target being migrated
+     */
+    pub(crate) target: String,
+
+    pub(crate) change_sets: Vec<ChangeSet>,
+}
+
+impl ExpectedPlan {
+    /**
+     * Fail with `PlanMismatchError` unless `found` has the same names and
+     * checksums as the plan.
+     */
+    pub(crate) fn check(
+        &self,
+        group_name: &str,
+        found: &[ChangeSet],
+    ) -> Result<(), MigrationError> {
+        if plan_key(found) == plan_key(&self.change_sets) {
+            return Ok(());
+        }
+        Err(MigrationError::PlanMismatchError {
+            group_name: group_name.to_string(),
+            expected_target: self.planned_on.clone(),
+            expected: plan_names(&self.change_sets),
+            target: self.target.clone(),
+            found: plan_names(found),
+        })
+    }
+}
+
+pub(crate) fn plan_names(change_sets: &[ChangeSet]) -> Vec<ChangeSetVersionName> {
+    change_sets.iter().map(|x| x.name.clone()).collect()
+}
+
+/**
+ * Names and checksums of planned change sets, which must be identical on
+ * every target
+ */
+pub(crate) fn plan_key(change_sets: &[ChangeSet]) -> Vec<(ChangeSetVersionName, String)> {
+    change_sets
+        .iter()
+        .map(|x| (x.name.clone(), x.checksum()))
+        .collect()
+}
+
+/**
+ * Source of down SQL used by rollback with local change sets.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RollbackSource {
+    /**
+     * Down SQL stored in the history table when the change set was applied
+     */
+    #[default]
+    Stored,
+
+    /**
+     * Down SQL in local files. A change set without local down SQL is
+     * irreversible.
+     */
+    Local,
+
+    /**
+     * Down SQL in local files if it exists, otherwise stored one
+     */
+    PreferLocal,
+}
+
+/**
+ * How local SQL is compared with SQL stored in history.
+ *
+ * Stored SQL is never rewritten to match local SQL which is considered the
+ * same.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SqlComparison {
+    /**
+     * Any byte difference is a change
+     */
+    #[default]
+    Exact,
+
+    /**
+     * Compare tokens. Comments, whitespace between tokens and line endings
+     * are ignored, while string literals and quoted identifiers are
+     * compared as written.
+     */
+    Normalized,
+}
+
+impl SqlComparison {
+    /**
+     * `true` if `a` and `b` are the same SQL.
+     */
+    pub fn same_sql(self, a: &str, b: &str) -> bool {
+        match self {
+            SqlComparison::Exact => a == b,
+            SqlComparison::Normalized => a == b || tokens(a) == tokens(b),
+        }
+    }
+
+    /**
+     * `true` if `a` and `b` are the same optional SQL such as down SQL.
+     */
+    pub fn same_optional_sql(self, a: Option<&str>, b: Option<&str>) -> bool {
+        match (a, b) {
+            (Some(a), Some(b)) => self.same_sql(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for SqlComparison {
+    type Err = MigrationError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "exact" => Ok(SqlComparison::Exact),
+            "normalized" => Ok(SqlComparison::Normalized),
+            _ => Err(MigrationError::InvalidValueError {
+                kind: "SQL comparison",
+                value: value.to_string(),
+            }),
+        }
+    }
+}
+
+impl FromStr for RollbackSource {
+    type Err = MigrationError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "stored" => Ok(RollbackSource::Stored),
+            "local" => Ok(RollbackSource::Local),
+            "prefer-local" => Ok(RollbackSource::PreferLocal),
+            _ => Err(MigrationError::InvalidValueError {
+                kind: "rollback source",
+                value: value.to_string(),
+            }),
+        }
+    }
+}
+
+impl Default for MigrationOptions {
+    fn default() -> Self {
+        MigrationOptions {
+            history_schema: None,
+            history_table: DEFAULT_HISTORY_TABLE.to_string(),
+            migration_lock: true,
+            migration_lock_timeout: None,
+            lock_timeout: None,
+            statement_timeout: None,
+            lock_retries: 0,
+            lock_retry_interval: Duration::from_secs(1),
+            allow_irreversible: false,
+            rollback_source: RollbackSource::Stored,
+            sql_comparison: SqlComparison::Exact,
+            hooks: Hooks::default(),
+            observers: Observers::default(),
+            expected_repair: None,
+            expected_plan: None,
+        }
+    }
+}
+
+impl MigrationOptions {
+    /**
+     * Set schema of the history table.
+     */
+    pub fn history_schema(mut self, schema: &str) -> Self {
+        self.history_schema = Some(schema.to_string());
+        self
+    }
+
+    /**
+     * Set name of the history table.
+     */
+    pub fn history_table(mut self, table: &str) -> Self {
+        self.history_table = table.to_string();
+        self
+    }
+
+    /**
+     * Enable or disable the migration lock.
+     */
+    pub fn migration_lock(mut self, enabled: bool) -> Self {
+        self.migration_lock = enabled;
+        self
+    }
+
+    /**
+     * Set how long to wait for the migration lock.
+     */
+    pub fn migration_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.migration_lock_timeout = Some(timeout);
+        self
+    }
+
+    /**
+     * Set `lock_timeout` while applying a change set.
+     */
+    pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = Some(timeout);
+        self
+    }
+
+    /**
+     * Set `statement_timeout` while applying a change set.
+     */
+    pub fn statement_timeout(mut self, timeout: Duration) -> Self {
+        self.statement_timeout = Some(timeout);
+        self
+    }
+
+    /**
+     * Retry a change set failed by `lock_timeout` up to `retries` times.
+     * The first retry waits `interval`, and the wait is doubled for each
+     * retry up to 30 seconds.
+     */
+    pub fn lock_retries(mut self, retries: u32, interval: Duration) -> Self {
+        self.lock_retries = retries;
+        self.lock_retry_interval = interval;
+        self
+    }
+
+    /**
+     * Allow or refuse rollback of irreversible change sets.
+     */
+    pub fn allow_irreversible(mut self, allowed: bool) -> Self {
+        self.allow_irreversible = allowed;
+        self
+    }
+
+    /**
+     * Set where down SQL is taken from when rolling back with local change
+     * sets.
+     */
+    pub fn rollback_source(mut self, source: RollbackSource) -> Self {
+        self.rollback_source = source;
+        self
+    }
+
+    /**
+     * Set how local SQL is compared with SQL stored in history.
+     */
+    pub fn sql_comparison(mut self, comparison: SqlComparison) -> Self {
+        self.sql_comparison = comparison;
+        self
+    }
+
+    /**
+     * Set hooks run around migrations and rollbacks.
+     */
+    pub fn hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /**
+     * Make `repair` apply exactly `actions` or fail.
+     */
+    pub fn expected_repair(mut self, actions: Vec<RepairAction>) -> Self {
+        self.expected_repair = Some(actions);
+        self
+    }
+
+    /**
+     * Add an observer which receives progress of migrations.
+     */
+    pub fn observer<O: MigrationObserver + 'static>(mut self, observer: O) -> Self {
+        self.observers = self.observers.observer(observer);
+        self
+    }
+
+    /**
+     * Wait before `attempt`-th retry, capped at `MAX_LOCK_RETRY_WAIT` or
+     * `lock_retry_interval` if it is longer
+     */
+    pub(crate) fn lock_retry_wait(&self, attempt: u32) -> Duration {
+        let max_wait = MAX_LOCK_RETRY_WAIT.max(self.lock_retry_interval);
+        self.lock_retry_interval
+            .checked_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .map_or(max_wait, |x| x.min(max_wait))
+    }
+
+    /**
+     * Quoted schema name of the history table.
+     */
+    pub(crate) fn quoted_history_schema(&self) -> Option<String> {
+        self.history_schema.as_deref().map(quote_identifier)
+    }
+
+    /**
+     * Quoted and schema qualified name of the history table.
+     */
+    pub(crate) fn quoted_history_table(&self) -> String {
+        match self.quoted_history_schema() {
+            Some(schema) => format!("{}.{}", schema, quote_identifier(&self.history_table)),
+            None => quote_identifier(&self.history_table),
+        }
+    }
+
+    /**
+     * Quoted and schema qualified name of the table which records the
+     * format version of the history table.
+     */
+    pub(crate) fn quoted_history_meta_table(&self) -> String {
+        self.quoted_history_sibling("meta")
+    }
+
+    /**
+     * Quoted and schema qualified name of the migration log table.
+     */
+    pub(crate) fn quoted_history_log_table(&self) -> String {
+        self.quoted_history_sibling("log")
+    }
+
+    fn quoted_history_sibling(&self, suffix: &str) -> String {
+        let table = quote_identifier(&format!("{}_{}", self.history_table, suffix));
+        match self.quoted_history_schema() {
+            Some(schema) => format!("{}.{}", schema, table),
+            None => table,
+        }
+    }
+}
+
+/**
+ * Quote an SQL identifier.
+ */
+pub(crate) fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/**
+ * A database client with migration options.
+ *
+ * ```no_run
+ * use asyncmigrate::{Migration, MigrationOptions, Migrator};
+ * # async fn run(changesets: asyncmigrate::MigrationChangeSets) -> Result<(), asyncmigrate::MigrationError> {
+ * let connection = asyncmigrate::connect("postgres://localhost/app").await?;
+ * let mut migrator = Migrator::with_options(
+ *     connection,
+ *     MigrationOptions::default()
+ *         .history_schema("ops")
+ *         .history_table("schema_history"),
+ * );
+ * migrator.migrate(&changesets, None).await?;
+ * # Ok(())
+ * # }
+ * ```
+ */
+pub struct Migrator<C> {
+    pub(crate) client: C,
+    pub(crate) options: MigrationOptions,
+}
+
+impl<C> Migrator<C> {
+    /**
+     * Create a migrator with default options.
+     */
+    pub fn new(client: C) -> Self {
+        Migrator::with_options(client, MigrationOptions::default())
+    }
+
+    /**
+     * Create a migrator with options.
+     */
+    pub fn with_options(client: C, options: MigrationOptions) -> Self {
+        Migrator { client, options }
+    }
+
+    /**
+     * Migration options
+     */
+    pub fn options(&self) -> &MigrationOptions {
+        &self.options
+    }
+
+    /**
+     * Mutable reference to migration options
+     */
+    pub fn options_mut(&mut self) -> &mut MigrationOptions {
+        &mut self.options
+    }
+
+    /**
+     * Underlying database client
+     */
+    pub fn client(&mut self) -> &mut C {
+        &mut self.client
+    }
+
+    /**
+     * Take the underlying database client.
+     */
+    pub fn into_inner(self) -> C {
+        self.client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quoted_history_table() {
+        assert_eq!(
+            MigrationOptions::default().quoted_history_table(),
+            "\"db_migration\""
+        );
+        assert_eq!(
+            MigrationOptions::default()
+                .history_schema("ops")
+                .history_table("Schema \"History\"")
+                .quoted_history_table(),
+            "\"ops\".\"Schema \"\"History\"\"\""
+        );
+        assert_eq!(
+            MigrationOptions::default()
+                .history_schema("ops")
+                .quoted_history_meta_table(),
+            "\"ops\".\"db_migration_meta\""
+        );
+        assert_eq!(
+            MigrationOptions::default().quoted_history_log_table(),
+            "\"db_migration_log\""
+        );
+    }
+
+    #[test]
+    fn test_parse_rollback_source() {
+        assert_eq!(
+            "stored".parse::<RollbackSource>().unwrap(),
+            RollbackSource::Stored
+        );
+        assert_eq!(
+            "local".parse::<RollbackSource>().unwrap(),
+            RollbackSource::Local
+        );
+        assert_eq!(
+            "prefer-local".parse::<RollbackSource>().unwrap(),
+            RollbackSource::PreferLocal
+        );
+        "newest".parse::<RollbackSource>().unwrap_err();
+    }
+
+    #[test]
+    fn test_sql_comparison() {
+        let applied = "CREATE TABLE a(\n    id INTEGER, -- key\n    note TEXT DEFAULT 'a  b'\n);\n";
+        let edited = "/* ticket 12 */\r\nCREATE TABLE a(\r\n  id INTEGER,  \r\n  note TEXT DEFAULT 'a  b'\r\n);";
+        assert!(!SqlComparison::Exact.same_sql(applied, edited));
+        assert!(SqlComparison::Normalized.same_sql(applied, edited));
+        assert!(!SqlComparison::Normalized.same_sql(
+            applied,
+            "CREATE TABLE a(id INTEGER, note TEXT DEFAULT 'a b');"
+        ));
+        assert!(!SqlComparison::Normalized.same_sql(
+            applied,
+            "CREATE TABLE a(id BIGINT, note TEXT DEFAULT 'a  b');"
+        ));
+        assert!(SqlComparison::Normalized.same_optional_sql(None, None));
+        assert!(!SqlComparison::Normalized.same_optional_sql(Some(applied), None));
+        assert_eq!(
+            "normalized".parse::<SqlComparison>().unwrap(),
+            SqlComparison::Normalized
+        );
+    }
+
+    #[test]
+    fn test_lock_retry_wait() {
+        let options = MigrationOptions::default().lock_retries(3, Duration::from_millis(500));
+        assert_eq!(options.lock_retry_wait(1), Duration::from_millis(500));
+        assert_eq!(options.lock_retry_wait(2), Duration::from_millis(1000));
+        assert_eq!(options.lock_retry_wait(3), Duration::from_millis(2000));
+        assert_eq!(options.lock_retry_wait(7), Duration::from_secs(30));
+        assert_eq!(options.lock_retry_wait(30), Duration::from_secs(30));
+        assert_eq!(options.lock_retry_wait(100), Duration::from_secs(30));
+        let options = MigrationOptions::default().lock_retries(3, Duration::from_secs(60));
+        assert_eq!(options.lock_retry_wait(1), Duration::from_secs(60));
+        assert_eq!(options.lock_retry_wait(3), Duration::from_secs(60));
+        let options = MigrationOptions::default().lock_retries(3, Duration::MAX);
+        assert_eq!(options.lock_retry_wait(2), Duration::MAX);
+    }
+}
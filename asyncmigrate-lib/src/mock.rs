@@ -0,0 +1,1188 @@
+//! In-memory backend for unit tests
+//!
+//! ```
+//! use asyncmigrate::mock::MockConnection;
+//! use asyncmigrate::{Migration, MigrationChangeSets};
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), asyncmigrate::MigrationError> {
+//! let changesets = MigrationChangeSets::load_dir("default", "./schema")?;
+//! let mut connection = MockConnection::new();
+//! connection.fail_migrate_at(200);
+//!
+//! assert!(connection.migrate(&changesets, None).await.is_err());
+//! assert_eq!(connection.executed_sql().len(), 4);
+//! assert_eq!(
+//!     connection
+//!         .load_applied_change_sets("default")
+//!         .await?
+//!         .change_sets
+//!         .len(),
+//!     3
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::changeset::{check_reversible, check_transactional};
+use crate::history::{hostname, TOOL_VERSION};
+use crate::repair::{check_expected_repair, plan_repair};
+use crate::{
+    AppliedInfo, ApplyStatus, ChangeSet, HistoryOperation, HookContext, HookEvent, Hooks, LogEntry,
+    Migration, MigrationChangeSets, MigrationError, RepairAction, RollbackSource, SqlComparison,
+};
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime};
+
+/**
+ * A database connection mock which keeps migration history in memory.
+ *
+ * No SQL is executed. Instead, SQL which would be executed is recorded.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct MockConnection {
+    history: HashMap<String, Vec<ChangeSet>>,
+    log: Vec<LogEntry>,
+    executed_sql: Vec<String>,
+    migrate_failures: HashSet<i32>,
+    rollback_failures: HashSet<i32>,
+    allow_irreversible: bool,
+    rollback_source: RollbackSource,
+    sql_comparison: SqlComparison,
+    hooks: Hooks,
+    expected_repair: Option<Vec<RepairAction>>,
+}
+
+impl MockConnection {
+    /**
+     * Create a mock with empty history.
+     */
+    pub fn new() -> Self {
+        MockConnection::default()
+    }
+
+    /**
+     * SQL which would be executed, in order of execution.
+     */
+    pub fn executed_sql(&self) -> &[String] {
+        &self.executed_sql
+    }
+
+    /**
+     * Clear recorded SQL.
+     */
+    pub fn clear_executed_sql(&mut self) {
+        self.executed_sql.clear();
+    }
+
+    /**
+     * Make applying a change set of `version` fail.
+     */
+    pub fn fail_migrate_at(&mut self, version: i32) -> &mut Self {
+        self.migrate_failures.insert(version);
+        self
+    }
+
+    /**
+     * Make reverting a change set of `version` fail.
+     */
+    pub fn fail_rollback_at(&mut self, version: i32) -> &mut Self {
+        self.rollback_failures.insert(version);
+        self
+    }
+
+    /**
+     * Allow or refuse rollback of irreversible change sets, as
+     * `MigrationOptions::allow_irreversible` does.
+     */
+    pub fn allow_irreversible(&mut self, allowed: bool) -> &mut Self {
+        self.allow_irreversible = allowed;
+        self
+    }
+
+    /**
+     * Set where down SQL is taken from when rolling back with local change
+     * sets, as `MigrationOptions::rollback_source` does.
+     */
+    pub fn rollback_source(&mut self, source: RollbackSource) -> &mut Self {
+        self.rollback_source = source;
+        self
+    }
+
+    /**
+     * Set how local SQL is compared with SQL stored in history, as
+     * `MigrationOptions::sql_comparison` does.
+     */
+    pub fn sql_comparison(&mut self, comparison: SqlComparison) -> &mut Self {
+        self.sql_comparison = comparison;
+        self
+    }
+
+    /**
+     * Set hooks run around migrations and rollbacks, as
+     * `MigrationOptions::hooks` does. Hook SQL is recorded with other SQL.
+     */
+    pub fn hooks(&mut self, hooks: Hooks) -> &mut Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /**
+     * Make `repair` apply exactly `actions` or fail, as
+     * `MigrationOptions::expected_repair` does.
+     */
+    pub fn expected_repair(&mut self, actions: Vec<RepairAction>) -> &mut Self {
+        self.expected_repair = Some(actions);
+        self
+    }
+
+    /**
+     * Remove all injected failures.
+     */
+    pub fn clear_failures(&mut self) {
+        self.migrate_failures.clear();
+        self.rollback_failures.clear();
+    }
+
+    /**
+     * Restore history and log saved at the beginning of a transaction, and
+     * log the failure which rolled it back.
+     */
+    fn abort_transaction(
+        &mut self,
+        history: HashMap<String, Vec<ChangeSet>>,
+        log_len: usize,
+        group_name: &str,
+        changeset: &ChangeSet,
+        operation: HistoryOperation,
+        error: MigrationError,
+    ) -> MigrationError {
+        self.history = history;
+        self.log.truncate(log_len);
+        self.append_log(group_name, changeset, operation, Some(&error));
+        error
+    }
+
+    fn run_hook(
+        &mut self,
+        event: HookEvent,
+        group_name: &str,
+        changeset: Option<&ChangeSet>,
+    ) -> Result<(), MigrationError> {
+        if let Some(sql) = self.hooks.get_sql(event) {
+            self.executed_sql.push(sql.to_string());
+        }
+        self.hooks.call(&HookContext {
+            event,
+            group_name,
+            change_set: changeset,
+        })
+    }
+
+    /**
+     * Record SQL of applying a change set with its hooks.
+     */
+    fn apply_one(&mut self, group_name: &str, changeset: &ChangeSet) -> Result<(), MigrationError> {
+        self.run_hook(HookEvent::BeforeEachMigrate, group_name, Some(changeset))?;
+        self.executed_sql.push(changeset.up_sql.clone());
+        if self.migrate_failures.contains(&changeset.name.version) {
+            return Err(MigrationError::InjectedFailureError {
+                group_name: group_name.to_string(),
+                version: changeset.name.version,
+                operation: HistoryOperation::Migrate,
+            });
+        }
+        self.run_hook(HookEvent::AfterEachMigrate, group_name, Some(changeset))
+    }
+
+    /**
+     * Record SQL of reverting a change set with its hooks.
+     */
+    fn revert_one(
+        &mut self,
+        group_name: &str,
+        changeset: &ChangeSet,
+    ) -> Result<(), MigrationError> {
+        self.run_hook(HookEvent::BeforeEachRollback, group_name, Some(changeset))?;
+        if changeset.is_reversible() {
+            if let Some(down_sql) = changeset.down_sql.as_ref() {
+                self.executed_sql.push(down_sql.clone());
+            }
+        }
+        if self.rollback_failures.contains(&changeset.name.version) {
+            return Err(MigrationError::InjectedFailureError {
+                group_name: group_name.to_string(),
+                version: changeset.name.version,
+                operation: HistoryOperation::Rollback,
+            });
+        }
+        self.run_hook(HookEvent::AfterEachRollback, group_name, Some(changeset))
+    }
+
+    fn pending_change_sets(
+        &self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<MigrationChangeSets, MigrationError> {
+        let db_migration_set = self.load_history(&changesets.group_name);
+        db_migration_set.check_clean()?;
+        let diff = changesets.calc_diff_with(&db_migration_set, self.sql_comparison)?;
+        Ok(if let Some(count) = count {
+            diff.subset(..count)
+        } else {
+            diff
+        })
+    }
+
+    fn plan_rollback_with(
+        &self,
+        group_name: &str,
+        local: Option<&MigrationChangeSets>,
+        count: Option<usize>,
+    ) -> Result<Vec<ChangeSet>, MigrationError> {
+        let targets = self.load_history(group_name).rollback_targets(count)?;
+        match local {
+            Some(local) => local.resolve_down_sql(targets, self.rollback_source),
+            None => Ok(targets),
+        }
+    }
+
+    fn rollback_with(
+        &mut self,
+        group_name: &str,
+        local: Option<&MigrationChangeSets>,
+        count: Option<usize>,
+    ) -> Result<(), MigrationError> {
+        let targets = self.plan_rollback_with(group_name, local, count)?;
+        if !self.allow_irreversible {
+            check_reversible(group_name, &targets)?;
+        }
+        self.run_hook(HookEvent::BeforeRollback, group_name, None)?;
+        for one in targets.iter() {
+            if let Err(error) = self.revert_one(group_name, one) {
+                self.append_log(group_name, one, HistoryOperation::Rollback, Some(&error));
+                return Err(error);
+            }
+            if let Some(history) = self.history.get_mut(group_name) {
+                history.pop();
+            }
+            self.append_log(group_name, one, HistoryOperation::Rollback, None);
+        }
+        self.run_hook(HookEvent::AfterRollback, group_name, None)
+    }
+
+    fn push_history(
+        &mut self,
+        group_name: &str,
+        changeset: &ChangeSet,
+        execution_time: Option<Duration>,
+        failure: Option<&MigrationError>,
+    ) {
+        self.history
+            .entry(group_name.to_string())
+            .or_default()
+            .push(ChangeSet {
+                applied: Some(AppliedInfo {
+                    applied_at: Some(SystemTime::now()),
+                    execution_time,
+                    applied_by: Some("mock".to_string()),
+                    hostname: hostname(),
+                    tool_version: Some(TOOL_VERSION.to_string()),
+                    checksum: Some(changeset.checksum()),
+                    status: if failure.is_some() {
+                        ApplyStatus::Failed
+                    } else {
+                        ApplyStatus::Applied
+                    },
+                    failed_statement: failure.map(|_| 0),
+                    error: failure.map(|x| x.to_string()),
+                }),
+                path: None,
+                ..changeset.clone()
+            });
+    }
+
+    fn append_log(
+        &mut self,
+        group_name: &str,
+        changeset: &ChangeSet,
+        operation: HistoryOperation,
+        error: Option<&MigrationError>,
+    ) {
+        self.log.push(LogEntry {
+            id: self.log.len() as i64 + 1,
+            logged_at: SystemTime::now(),
+            group_name: group_name.to_string(),
+            version: Some(changeset.name.version),
+            name: Some(changeset.name.name.clone()),
+            operation: operation.as_str().to_string(),
+            success: error.is_none(),
+            actor: "mock".to_string(),
+            hostname: hostname(),
+            tool_version: Some(TOOL_VERSION.to_string()),
+            error: error.map(|x| x.to_string()),
+        });
+    }
+
+    fn load_history(&self, group_name: &str) -> MigrationChangeSets {
+        MigrationChangeSets {
+            group_name: group_name.to_string(),
+            change_sets: self.history.get(group_name).cloned().unwrap_or_default(),
+        }
+    }
+}
+
+#[async_trait]
+impl Migration for MockConnection {
+    async fn migrate(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<(), MigrationError> {
+        let apply_diff = self.pending_change_sets(changesets, count)?;
+        self.run_hook(HookEvent::BeforeMigrate, &changesets.group_name, None)?;
+        for one in apply_diff.change_sets.iter() {
+            if let Err(error) = self.apply_one(&changesets.group_name, one) {
+                if one.options.no_transaction && self.migrate_failures.contains(&one.name.version) {
+                    // statements before the failed one are not rolled back
+                    self.push_history(&changesets.group_name, one, None, Some(&error));
+                }
+                self.append_log(
+                    &changesets.group_name,
+                    one,
+                    HistoryOperation::Migrate,
+                    Some(&error),
+                );
+                return Err(error);
+            }
+            self.append_log(&changesets.group_name, one, HistoryOperation::Migrate, None);
+            self.push_history(
+                &changesets.group_name,
+                one,
+                Some(Duration::from_secs(0)),
+                None,
+            );
+        }
+        self.run_hook(HookEvent::AfterMigrate, &changesets.group_name, None)
+    }
+
+    async fn plan_migrate(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<Vec<ChangeSet>, MigrationError> {
+        Ok(self.pending_change_sets(changesets, count)?.change_sets)
+    }
+
+    async fn migrate_atomic(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<(), MigrationError> {
+        let apply_diff = self.pending_change_sets(changesets, count)?;
+        check_transactional(&changesets.group_name, &apply_diff.change_sets)?;
+        self.run_hook(HookEvent::BeforeMigrate, &changesets.group_name, None)?;
+        let history = self.history.clone();
+        let log_len = self.log.len();
+        for one in apply_diff.change_sets.iter() {
+            if let Err(error) = self.apply_one(&changesets.group_name, one) {
+                return Err(self.abort_transaction(
+                    history,
+                    log_len,
+                    &changesets.group_name,
+                    one,
+                    HistoryOperation::Migrate,
+                    error,
+                ));
+            }
+            self.append_log(&changesets.group_name, one, HistoryOperation::Migrate, None);
+            self.push_history(
+                &changesets.group_name,
+                one,
+                Some(Duration::from_secs(0)),
+                None,
+            );
+        }
+        if let Err(error) = self.run_hook(HookEvent::AfterMigrate, &changesets.group_name, None) {
+            self.history = history;
+            self.log.truncate(log_len);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    async fn redo(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: usize,
+    ) -> Result<(), MigrationError> {
+        let group_name = &changesets.group_name;
+        let (revert, reapply) = changesets.redo_plan(
+            &self.load_history(group_name),
+            count,
+            self.rollback_source,
+            self.sql_comparison,
+        )?;
+        if !self.allow_irreversible {
+            check_reversible(group_name, &revert)?;
+        }
+        self.run_hook(HookEvent::BeforeRollback, group_name, None)?;
+        let history = self.history.clone();
+        let log_len = self.log.len();
+        for one in revert.iter() {
+            if let Err(error) = self.revert_one(group_name, one) {
+                return Err(self.abort_transaction(
+                    history,
+                    log_len,
+                    group_name,
+                    one,
+                    HistoryOperation::Rollback,
+                    error,
+                ));
+            }
+            if let Some(history) = self.history.get_mut(group_name) {
+                history.pop();
+            }
+            self.append_log(group_name, one, HistoryOperation::Rollback, None);
+        }
+        if let Err(error) = self
+            .run_hook(HookEvent::AfterRollback, group_name, None)
+            .and_then(|_| self.run_hook(HookEvent::BeforeMigrate, group_name, None))
+        {
+            self.history = history;
+            self.log.truncate(log_len);
+            return Err(error);
+        }
+        for one in reapply.iter() {
+            if let Err(error) = self.apply_one(group_name, one) {
+                return Err(self.abort_transaction(
+                    history,
+                    log_len,
+                    group_name,
+                    one,
+                    HistoryOperation::Migrate,
+                    error,
+                ));
+            }
+            self.append_log(group_name, one, HistoryOperation::Migrate, None);
+            self.push_history(group_name, one, Some(Duration::from_secs(0)), None);
+        }
+        if let Err(error) = self.run_hook(HookEvent::AfterMigrate, group_name, None) {
+            self.history = history;
+            self.log.truncate(log_len);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    async fn update_rollback_sql(
+        &mut self,
+        changesets: &MigrationChangeSets,
+    ) -> Result<(), MigrationError> {
+        let history = self.load_history(&changesets.group_name);
+        for (i, (local, db)) in changesets
+            .change_sets
+            .iter()
+            .zip(history.change_sets.iter())
+            .enumerate()
+        {
+            changesets.check_name(local, db)?;
+            if !self
+                .sql_comparison
+                .same_optional_sql(local.down_sql.as_deref(), db.down_sql.as_deref())
+            {
+                if let Some(history) = self.history.get_mut(&changesets.group_name) {
+                    history[i].down_sql = local.down_sql.clone();
+                }
+                self.append_log(
+                    &changesets.group_name,
+                    local,
+                    HistoryOperation::UpdateRollbackSql,
+                    None,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn rollback(
+        &mut self,
+        group_name: &str,
+        count: Option<usize>,
+    ) -> Result<(), MigrationError> {
+        self.rollback_with(group_name, None, count)
+    }
+
+    async fn plan_rollback(
+        &mut self,
+        group_name: &str,
+        count: Option<usize>,
+    ) -> Result<Vec<ChangeSet>, MigrationError> {
+        self.plan_rollback_with(group_name, None, count)
+    }
+
+    async fn rollback_with_local(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<(), MigrationError> {
+        self.rollback_with(&changesets.group_name, Some(changesets), count)
+    }
+
+    async fn plan_rollback_with_local(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        count: Option<usize>,
+    ) -> Result<Vec<ChangeSet>, MigrationError> {
+        self.plan_rollback_with(&changesets.group_name, Some(changesets), count)
+    }
+
+    async fn baseline(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        up_to_version: i32,
+    ) -> Result<(), MigrationError> {
+        if !self
+            .load_history(&changesets.group_name)
+            .change_sets
+            .is_empty()
+        {
+            return Err(MigrationError::HistoryExistsError {
+                group_name: changesets.group_name.clone(),
+            });
+        }
+        let baseline_set = changesets.baseline_subset(up_to_version)?;
+        for one in baseline_set.change_sets.iter() {
+            self.push_history(&changesets.group_name, one, None, None);
+            self.append_log(
+                &changesets.group_name,
+                one,
+                HistoryOperation::Baseline,
+                None,
+            );
+        }
+        Ok(())
+    }
+
+    async fn mark_applied(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        version: i32,
+    ) -> Result<(), MigrationError> {
+        let changeset = changesets.next_change_set(
+            &self.load_history(&changesets.group_name),
+            version,
+            self.sql_comparison,
+        )?;
+        self.push_history(&changesets.group_name, &changeset, None, None);
+        self.append_log(
+            &changesets.group_name,
+            &changeset,
+            HistoryOperation::MarkApplied,
+            None,
+        );
+        Ok(())
+    }
+
+    async fn mark_reverted(
+        &mut self,
+        group_name: &str,
+        version: i32,
+    ) -> Result<(), MigrationError> {
+        let changeset = self.load_history(group_name).last_change_set(version)?;
+        if let Some(history) = self.history.get_mut(group_name) {
+            history.pop();
+        }
+        self.append_log(group_name, &changeset, HistoryOperation::MarkReverted, None);
+        Ok(())
+    }
+
+    async fn plan_repair(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        versions: Option<&[i32]>,
+    ) -> Result<Vec<RepairAction>, MigrationError> {
+        Ok(plan_repair(
+            changesets,
+            &self.load_history(&changesets.group_name),
+            versions,
+            self.sql_comparison,
+        ))
+    }
+
+    async fn repair(
+        &mut self,
+        changesets: &MigrationChangeSets,
+        versions: Option<&[i32]>,
+    ) -> Result<Vec<RepairAction>, MigrationError> {
+        let actions = self.plan_repair(changesets, versions).await?;
+        check_expected_repair(
+            &changesets.group_name,
+            self.expected_repair.as_deref(),
+            &actions,
+        )?;
+        let history = self
+            .history
+            .entry(changesets.group_name.clone())
+            .or_default();
+        for one in actions.iter() {
+            match one {
+                RepairAction::Update { local, .. } => {
+                    for stored in history.iter_mut() {
+                        if stored.name.version == local.name.version {
+                            if let Some(applied) = stored.applied.as_mut() {
+                                applied.checksum = Some(local.checksum());
+                            }
+                            stored.name = local.name.clone();
+                            stored.up_sql = local.up_sql.clone();
+                            stored.down_sql = local.down_sql.clone();
+                        }
+                    }
+                }
+                RepairAction::Remove(stored) => {
+                    history.retain(|x| x.name.version != stored.name.version);
+                }
+                RepairAction::Resolve(resolved) => {
+                    for stored in history.iter_mut() {
+                        if stored.name.version == resolved.name.version {
+                            if let Some(applied) = stored.applied.as_mut() {
+                                applied.status = ApplyStatus::Applied;
+                                applied.failed_statement = None;
+                                applied.error = None;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        for one in actions.iter() {
+            self.append_log(
+                &changesets.group_name,
+                one.stored(),
+                HistoryOperation::Repair,
+                None,
+            );
+        }
+        Ok(actions)
+    }
+
+    async fn load_applied_change_sets(
+        &mut self,
+        group_name: &str,
+    ) -> Result<MigrationChangeSets, MigrationError> {
+        Ok(self.load_history(group_name))
+    }
+
+    async fn load_log(
+        &mut self,
+        group_name: Option<&str>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<LogEntry>, MigrationError> {
+        Ok(self
+            .log
+            .iter()
+            .rev()
+            .filter(|x| group_name.map(|y| x.group_name == y).unwrap_or(true))
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChangeSetOptions, ChangeSetVersionName, MismatchKind};
+    use std::sync::{Arc, Mutex};
+
+    fn names(change_sets: &MigrationChangeSets) -> Vec<ChangeSetVersionName> {
+        change_sets
+            .change_sets
+            .iter()
+            .map(|x| x.name.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_mock_migrate_and_rollback() {
+        let mut change_sets = MigrationChangeSets::load_dir("generic", "./schema").unwrap();
+        // V11 without down SQL cannot be reverted
+        change_sets.change_sets[2].down_sql = None;
+        let mut connection = MockConnection::new();
+
+        let plan = connection
+            .plan_migrate(&change_sets, Some(2))
+            .await
+            .unwrap();
+        assert_eq!(
+            plan.iter().map(|x| x.name.version).collect::<Vec<_>>(),
+            vec![1, 10]
+        );
+        assert!(connection.executed_sql().is_empty());
+
+        connection.migrate(&change_sets, Some(2)).await.unwrap();
+        assert_eq!(
+            connection.executed_sql(),
+            &[
+                change_sets.change_sets[0].up_sql.clone(),
+                change_sets.change_sets[1].up_sql.clone()
+            ]
+        );
+
+        connection.migrate(&change_sets, None).await.unwrap();
+        assert_eq!(
+            names(
+                &connection
+                    .load_applied_change_sets("generic")
+                    .await
+                    .unwrap()
+            ),
+            names(&change_sets)
+        );
+
+        connection.clear_executed_sql();
+        let plan = connection.plan_rollback("generic", Some(2)).await.unwrap();
+        assert_eq!(
+            plan.iter()
+                .map(|x| (x.name.version, x.is_reversible()))
+                .collect::<Vec<_>>(),
+            vec![(200, true), (11, false)]
+        );
+        match connection.rollback("generic", Some(2)).await {
+            Err(MigrationError::IrreversibleError { versions, .. }) => {
+                assert_eq!(versions, vec![11])
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(connection.executed_sql().is_empty());
+
+        connection.allow_irreversible(true);
+        connection.rollback("generic", Some(2)).await.unwrap();
+        assert_eq!(
+            connection.executed_sql(),
+            change_sets.change_sets[2..]
+                .iter()
+                .rev()
+                .flat_map(|x| x.down_sql.clone())
+                .collect::<Vec<_>>()
+                .as_slice()
+        );
+        assert_eq!(
+            names(
+                &connection
+                    .load_applied_change_sets("generic")
+                    .await
+                    .unwrap()
+            ),
+            names(&change_sets.subset(..2))
+        );
+        connection.rollback("generic", Some(3)).await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_mock_rollback_with_local() {
+        let change_sets = MigrationChangeSets::load_dir("generic", "./schema").unwrap();
+        let mut connection = MockConnection::new();
+        connection.migrate(&change_sets, None).await.unwrap();
+
+        let mut local = change_sets.clone();
+        local.change_sets[3].down_sql = Some("DROP TABLE IF EXISTS new_table;".to_string());
+        connection.clear_executed_sql();
+        connection
+            .rollback_source(RollbackSource::PreferLocal)
+            .rollback_with_local(&local, Some(1))
+            .await
+            .unwrap();
+        assert_eq!(
+            connection.executed_sql(),
+            &["DROP TABLE IF EXISTS new_table;".to_string()]
+        );
+
+        local.change_sets[2].name.name = "renamed".to_string();
+        connection
+            .rollback_with_local(&local, Some(1))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            connection
+                .load_applied_change_sets("generic")
+                .await
+                .unwrap()
+                .change_sets
+                .len(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mock_sql_comparison() {
+        let change_sets = MigrationChangeSets::load_dir("generic", "./schema").unwrap();
+        let mut connection = MockConnection::new();
+        connection.migrate(&change_sets, Some(2)).await.unwrap();
+
+        // checked out on Windows and commented afterwards
+        let mut local = change_sets.clone();
+        for one in local.change_sets.iter_mut() {
+            one.up_sql = format!("-- reviewed\r\n{}  \r\n", one.up_sql.replace('\n', "\r\n"));
+        }
+        connection.migrate(&local, None).await.unwrap_err();
+
+        connection.sql_comparison(SqlComparison::Normalized);
+        connection.clear_executed_sql();
+        connection.migrate(&local, None).await.unwrap();
+        assert_eq!(connection.executed_sql().len(), 2);
+        let applied = connection
+            .load_applied_change_sets("generic")
+            .await
+            .unwrap();
+        assert_eq!(
+            applied.change_sets[0].up_sql,
+            change_sets.change_sets[0].up_sql
+        );
+        assert!(connection
+            .plan_repair(&local, None)
+            .await
+            .unwrap()
+            .is_empty());
+
+        local.change_sets[0].up_sql = local.change_sets[0].up_sql.replace("TEXT", "VARCHAR");
+        match connection.migrate(&local, None).await {
+            Err(MigrationError::ChangeSetMismatchError {
+                version: 1,
+                kind: MismatchKind::UpSql,
+                path,
+                diff,
+                ..
+            }) => {
+                assert!(path.unwrap().ends_with("001__setup/up.sql"));
+                assert!(diff
+                    .lines()
+                    .any(|x| x.starts_with("+ ") && x.contains("VARCHAR")));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_migrate_atomic() {
+        let change_sets = MigrationChangeSets::load_dir("generic", "./schema").unwrap();
+        let mut connection = MockConnection::new();
+        connection.migrate(&change_sets, Some(1)).await.unwrap();
+
+        connection.fail_migrate_at(200);
+        connection
+            .migrate_atomic(&change_sets, None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            names(
+                &connection
+                    .load_applied_change_sets("generic")
+                    .await
+                    .unwrap()
+            ),
+            names(&change_sets.subset(..1))
+        );
+        let log = connection.load_log(None, 0, 10).await.unwrap();
+        assert_eq!(
+            log.iter()
+                .map(|x| (x.version, x.success))
+                .collect::<Vec<_>>(),
+            vec![(Some(200), false), (Some(1), true)]
+        );
+
+        connection.clear_failures();
+        connection.migrate_atomic(&change_sets, None).await.unwrap();
+        assert_eq!(
+            names(
+                &connection
+                    .load_applied_change_sets("generic")
+                    .await
+                    .unwrap()
+            ),
+            names(&change_sets)
+        );
+
+        let mut concurrent = change_sets.clone();
+        concurrent.change_sets.push(ChangeSet {
+            name: ChangeSetVersionName::new(300, "concurrently"),
+            up_sql: "CREATE INDEX CONCURRENTLY new_table_id ON new_table(id);".to_string(),
+            down_sql: None,
+            options: ChangeSetOptions {
+                no_transaction: true,
+                ..ChangeSetOptions::default()
+            },
+            applied: None,
+            path: None,
+        });
+        match connection.migrate_atomic(&concurrent, None).await {
+            Err(MigrationError::NoTransactionError { version: 300, .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_redo() {
+        let mut change_sets = MigrationChangeSets::load_dir("generic", "./schema").unwrap();
+        change_sets.change_sets[2].down_sql = None;
+        let mut connection = MockConnection::new();
+        connection.migrate(&change_sets, None).await.unwrap();
+
+        let mut edited = change_sets.clone();
+        edited.change_sets[3].up_sql = "CREATE TABLE new_table(id BIGINT PRIMARY KEY);".to_string();
+        connection.fail_migrate_at(200);
+        connection.clear_executed_sql();
+        connection.redo(&edited, 1).await.unwrap_err();
+        assert_eq!(
+            connection.executed_sql(),
+            &[
+                change_sets.change_sets[3].down_sql.clone().unwrap(),
+                edited.change_sets[3].up_sql.clone()
+            ]
+        );
+        let applied = connection
+            .load_applied_change_sets("generic")
+            .await
+            .unwrap();
+        assert_eq!(
+            applied.change_sets[3].up_sql,
+            change_sets.change_sets[3].up_sql
+        );
+
+        connection.clear_failures();
+        connection.redo(&edited, 1).await.unwrap();
+        let applied = connection
+            .load_applied_change_sets("generic")
+            .await
+            .unwrap();
+        assert_eq!(applied.change_sets[3].up_sql, edited.change_sets[3].up_sql);
+        assert_eq!(
+            connection
+                .load_log(None, 0, 3)
+                .await
+                .unwrap()
+                .iter()
+                .map(|x| (x.operation.as_str(), x.success))
+                .collect::<Vec<_>>(),
+            vec![("migrate", true), ("rollback", true), ("migrate", false)]
+        );
+
+        // V11 has no down SQL
+        connection.redo(&edited, 2).await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_mock_hooks() {
+        let change_sets = MigrationChangeSets::load_dir("generic", "./schema").unwrap();
+        let versions = Arc::new(Mutex::new(Vec::new()));
+        let recorder = versions.clone();
+        let mut connection = MockConnection::new();
+        connection.hooks(
+            Hooks::new()
+                .sql(HookEvent::BeforeMigrate, "SET ROLE owner;")
+                .sql(HookEvent::AfterRollback, "RESET ROLE;")
+                .callback(HookEvent::AfterEachMigrate, move |context| {
+                    recorder
+                        .lock()
+                        .unwrap()
+                        .push(context.change_set.unwrap().name.version);
+                    Ok(())
+                }),
+        );
+        connection.migrate(&change_sets, Some(2)).await.unwrap();
+        assert_eq!(
+            connection.executed_sql(),
+            &[
+                "SET ROLE owner;".to_string(),
+                change_sets.change_sets[0].up_sql.clone(),
+                change_sets.change_sets[1].up_sql.clone(),
+            ]
+        );
+        assert_eq!(*versions.lock().unwrap(), vec![1, 10]);
+
+        connection.clear_executed_sql();
+        connection.rollback("generic", Some(1)).await.unwrap();
+        assert_eq!(
+            connection.executed_sql(),
+            &[
+                change_sets.change_sets[1].down_sql.clone().unwrap(),
+                "RESET ROLE;".to_string(),
+            ]
+        );
+
+        // a failing callback fails the change set
+        connection.hooks(
+            Hooks::new().callback(HookEvent::BeforeEachMigrate, |context| {
+                Err(MigrationError::HookRefusedError {
+                    event: context.event,
+                    message: "not now".to_string(),
+                })
+            }),
+        );
+        connection.clear_executed_sql();
+        connection.migrate(&change_sets, None).await.unwrap_err();
+        assert!(connection.executed_sql().is_empty());
+        let log = connection.load_log(None, 0, 1).await.unwrap();
+        assert!(!log[0].success);
+    }
+
+    #[tokio::test]
+    async fn test_mock_failure() {
+        let change_sets = MigrationChangeSets::load_dir("generic", "./schema").unwrap();
+        let mut connection = MockConnection::new();
+        connection.fail_migrate_at(11).fail_rollback_at(1);
+
+        connection.migrate(&change_sets, None).await.unwrap_err();
+        assert_eq!(connection.executed_sql().len(), 3);
+        assert_eq!(
+            names(
+                &connection
+                    .load_applied_change_sets("generic")
+                    .await
+                    .unwrap()
+            ),
+            names(&change_sets.subset(..2))
+        );
+
+        connection.rollback("generic", None).await.unwrap_err();
+        assert_eq!(
+            names(
+                &connection
+                    .load_applied_change_sets("generic")
+                    .await
+                    .unwrap()
+            ),
+            names(&change_sets.subset(..1))
+        );
+
+        let log = connection.load_log(Some("generic"), 0, 2).await.unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].operation, "rollback");
+        assert_eq!(log[0].version, Some(1));
+        assert!(!log[0].success);
+        assert_eq!(log[1].operation, "rollback");
+        assert_eq!(log[1].version, Some(10));
+        assert!(log[1].success);
+
+        connection.clear_failures();
+        connection.migrate(&change_sets, None).await.unwrap();
+        assert_eq!(
+            names(
+                &connection
+                    .load_applied_change_sets("generic")
+                    .await
+                    .unwrap()
+            ),
+            names(&change_sets)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mock_baseline() {
+        let change_sets = MigrationChangeSets::load_dir("generic", "./schema").unwrap();
+        let mut connection = MockConnection::new();
+
+        connection.baseline(&change_sets, 10).await.unwrap();
+        assert!(connection.executed_sql().is_empty());
+        assert_eq!(
+            names(
+                &connection
+                    .load_applied_change_sets("generic")
+                    .await
+                    .unwrap()
+            ),
+            names(&change_sets.subset(..2))
+        );
+        connection.baseline(&change_sets, 10).await.unwrap_err();
+
+        connection.migrate(&change_sets, None).await.unwrap();
+        assert_eq!(connection.executed_sql().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_mock_mark_applied_and_reverted() {
+        let change_sets = MigrationChangeSets::load_dir("generic", "./schema").unwrap();
+        let mut connection = MockConnection::new();
+
+        connection.mark_applied(&change_sets, 10).await.unwrap_err();
+        connection.mark_applied(&change_sets, 1).await.unwrap();
+        connection.mark_applied(&change_sets, 10).await.unwrap();
+        assert!(connection.executed_sql().is_empty());
+        assert_eq!(
+            names(
+                &connection
+                    .load_applied_change_sets("generic")
+                    .await
+                    .unwrap()
+            ),
+            names(&change_sets.subset(..2))
+        );
+
+        connection.mark_reverted("generic", 1).await.unwrap_err();
+        connection.mark_reverted("generic", 10).await.unwrap();
+        assert!(connection.executed_sql().is_empty());
+        assert_eq!(
+            names(
+                &connection
+                    .load_applied_change_sets("generic")
+                    .await
+                    .unwrap()
+            ),
+            names(&change_sets.subset(..1))
+        );
+        assert_eq!(
+            connection.load_log(None, 0, 1).await.unwrap()[0].operation,
+            "mark_reverted"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mock_dirty() {
+        let mut change_sets = MigrationChangeSets::load_dir("generic", "./schema").unwrap();
+        change_sets.change_sets[1].options.no_transaction = true;
+        let mut connection = MockConnection::new();
+        connection.fail_migrate_at(10);
+
+        connection.migrate(&change_sets, None).await.unwrap_err();
+        connection.clear_failures();
+        match connection.migrate(&change_sets, None).await {
+            Err(MigrationError::DirtyError {
+                version: 10,
+                status: ApplyStatus::Failed,
+                ..
+            }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        connection.rollback("generic", None).await.unwrap_err();
+
+        let actions = connection.plan_repair(&change_sets, None).await.unwrap();
+        assert_eq!(actions.len(), 1);
+        connection.expected_repair(Vec::new());
+        match connection.repair(&change_sets, None).await {
+            Err(MigrationError::RepairMismatchError {
+                expected, found, ..
+            }) => {
+                assert!(expected.is_empty());
+                assert_eq!(found, vec![10]);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        connection.expected_repair(actions.clone());
+        assert_eq!(
+            connection.repair(&change_sets, None).await.unwrap(),
+            actions
+        );
+        connection.migrate(&change_sets, None).await.unwrap();
+        assert_eq!(
+            names(
+                &connection
+                    .load_applied_change_sets("generic")
+                    .await
+                    .unwrap()
+            ),
+            names(&change_sets)
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio_postgres::config::{Config, Host};
+
+use crate::{MigrationError, MigrationEvent, Observers};
+
+const DEFAULT_PORT: u16 = 5432;
+
+/**
+ * Build a PostgreSQL connection configuration in the same manner as libpq.
+ *
+ * `conninfo` can be a `postgres://` or `postgresql://` URL, or a key-value
+ * string such as `host=/var/run/postgresql dbname=app`. Parameters missing
+ * from `conninfo` are filled from `PGHOST`, `PGPORT`, `PGUSER`,
+ * `PGPASSWORD`, `PGDATABASE`, `PGAPPNAME`, `PGOPTIONS` and
+ * `PGCONNECT_TIMEOUT`. If no password is given, the password file
+ * (`PGPASSFILE` or `~/.pgpass`) is searched, and a password file ignored
+ * for its permissions is reported to `observers`.
+ */
+pub fn load_config(conninfo: &str, observers: &Observers) -> Result<Config, MigrationError> {
+    let mut config: Config = conninfo.parse()?;
+    apply_environment(&mut config, |x| env::var(x).ok())?;
+
+    if config.get_password().is_none() {
+        if let Some(path) = password_file_path() {
+            if let Some(password) = lookup_password_file(&path, &config, observers)? {
+                config.password(password);
+            }
+        }
+    }
+
+    Ok(config)
+}
+
+/**
+ * Fill unspecified parameters with libpq environment variables.
+ */
+fn apply_environment<F>(config: &mut Config, var: F) -> Result<(), MigrationError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    if config.get_hosts().is_empty() && config.get_hostaddrs().is_empty() {
+        let hosts = var("PGHOST").unwrap_or_else(default_host);
+        for one in hosts.split(',') {
+            config.host(one);
+        }
+    }
+    if config.get_ports().is_empty() {
+        if let Some(ports) = var("PGPORT") {
+            for one in ports.split(',') {
+                config.port(one.parse()?);
+            }
+        }
+    }
+    if config.get_user().is_none() {
+        if let Some(user) = var("PGUSER")
+            .or_else(|| var("USER"))
+            .or_else(|| var("USERNAME"))
+        {
+            config.user(&user);
+        }
+    }
+    if config.get_password().is_none() {
+        if let Some(password) = var("PGPASSWORD") {
+            config.password(password);
+        }
+    }
+    if config.get_dbname().is_none() {
+        if let Some(dbname) = var("PGDATABASE") {
+            config.dbname(&dbname);
+        }
+    }
+    if config.get_application_name().is_none() {
+        if let Some(application_name) = var("PGAPPNAME") {
+            config.application_name(&application_name);
+        }
+    }
+    if config.get_options().is_none() {
+        if let Some(options) = var("PGOPTIONS") {
+            config.options(&options);
+        }
+    }
+    if config.get_connect_timeout().is_none() {
+        if let Some(timeout) = var("PGCONNECT_TIMEOUT") {
+            let timeout: i64 = timeout.parse()?;
+            if timeout > 0 {
+                config.connect_timeout(Duration::from_secs(timeout as u64));
+            }
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Default host when neither a connection string nor `PGHOST` specifies one.
+ *
+ * Like libpq, prefer a local UNIX domain socket if one is available.
+ */
+fn default_host() -> String {
+    #[cfg(unix)]
+    {
+        for dir in &["/var/run/postgresql", "/tmp"] {
+            if Path::new(dir)
+                .join(format!(".s.PGSQL.{}", DEFAULT_PORT))
+                .exists()
+            {
+                return dir.to_string();
+            }
+        }
+    }
+    "localhost".to_string()
+}
+
+fn password_file_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("PGPASSFILE") {
+        return Some(PathBuf::from(path));
+    }
+    if cfg!(windows) {
+        env::var_os("APPDATA").map(|x| Path::new(&x).join("postgresql").join("pgpass.conf"))
+    } else {
+        env::var_os("HOME").map(|x| Path::new(&x).join(".pgpass"))
+    }
+}
+
+/**
+ * Find a password for the first host of `config` in a password file.
+ *
+ * As libpq does, a password file readable by group or others is ignored.
+ */
+fn lookup_password_file(
+    path: &Path,
+    config: &Config,
+    observers: &Observers,
+) -> Result<Option<String>, MigrationError> {
+    let metadata = match fs::metadata(path) {
+        Ok(m) => m,
+        Err(_) => return Ok(None),
+    };
+    if !metadata.is_file() {
+        return Ok(None);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o077 != 0 {
+            observers.emit(MigrationEvent::PasswordFileIgnored {
+                path: path.to_path_buf(),
+            });
+            return Ok(None);
+        }
+    }
+
+    let host = match config.get_hosts().first() {
+        Some(Host::Tcp(x)) => x.to_string(),
+        #[cfg(unix)]
+        Some(Host::Unix(_)) => "localhost".to_string(),
+        None => match config.get_hostaddrs().first() {
+            Some(x) => x.to_string(),
+            None => "localhost".to_string(),
+        },
+    };
+    let port = config
+        .get_ports()
+        .first()
+        .copied()
+        .unwrap_or(DEFAULT_PORT)
+        .to_string();
+    let user = config.get_user().unwrap_or("");
+    let dbname = config.get_dbname().unwrap_or(user);
+
+    Ok(find_password(
+        &fs::read_to_string(path)?,
+        &[&host, &port, dbname, user],
+    ))
+}
+
+/**
+ * Search `hostname:port:database:username:password` lines of a password
+ * file and return the password of the first matching line.
+ */
+fn find_password(data: &str, keys: &[&str; 4]) -> Option<String> {
+    for line in data.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = split_password_line(line);
+        if fields.len() < 5 {
+            continue;
+        }
+        if fields
+            .iter()
+            .zip(keys.iter())
+            .all(|(field, key)| field == "*" || field == key)
+        {
+            return Some(fields[4].to_string());
+        }
+    }
+    None
+}
+
+/**
+ * Split a password file line by `:`. `\:` and `\\` are unescaped.
+ * The fifth field, the password, takes the rest of the line.
+ */
+fn split_password_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    fields.last_mut().unwrap().push(next);
+                }
+            }
+            ':' if fields.len() < 5 => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_conninfo() {
+        let config = load_config(
+            "host=/var/run/postgresql dbname=app user=ops password=x",
+            &Observers::default(),
+        )
+        .unwrap();
+        assert_eq!(config.get_dbname(), Some("app"));
+        assert_eq!(config.get_user(), Some("ops"));
+        #[cfg(unix)]
+        assert_eq!(
+            config.get_hosts(),
+            &[Host::Unix(PathBuf::from("/var/run/postgresql"))]
+        );
+
+        let config = load_config(
+            "postgresql://ops:x@db.example.com:6543/app",
+            &Observers::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            config.get_hosts(),
+            &[Host::Tcp("db.example.com".to_string())]
+        );
+        assert_eq!(config.get_ports(), &[6543]);
+        assert_eq!(config.get_password(), Some(&b"x"[..]));
+    }
+
+    #[test]
+    fn test_apply_environment() {
+        let env: HashMap<&str, &str> = [
+            ("PGHOST", "db1.example.com,db2.example.com"),
+            ("PGPORT", "6543"),
+            ("PGUSER", "ops"),
+            ("PGPASSWORD", "secret"),
+            ("PGDATABASE", "app"),
+            ("PGCONNECT_TIMEOUT", "10"),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        let var = |x: &str| env.get(x).map(|y| y.to_string());
+
+        let mut config: Config = "dbname=override".parse().unwrap();
+        apply_environment(&mut config, var).unwrap();
+        assert_eq!(
+            config.get_hosts(),
+            &[
+                Host::Tcp("db1.example.com".to_string()),
+                Host::Tcp("db2.example.com".to_string())
+            ]
+        );
+        assert_eq!(config.get_ports(), &[6543]);
+        assert_eq!(config.get_user(), Some("ops"));
+        assert_eq!(config.get_password(), Some(&b"secret"[..]));
+        assert_eq!(config.get_dbname(), Some("override"));
+        assert_eq!(config.get_connect_timeout(), Some(&Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_find_password() {
+        let data = "# comment\n\
+                    db.example.com:5432:app:ops:first\n\
+                    *:*:*:ops:wild\\:card\n\
+                    localhost:5432:*:admin:pass:with:colon\n";
+        assert_eq!(
+            find_password(data, &["db.example.com", "5432", "app", "ops"]),
+            Some("first".to_string())
+        );
+        assert_eq!(
+            find_password(data, &["other", "6543", "app", "ops"]),
+            Some("wild:card".to_string())
+        );
+        assert_eq!(
+            find_password(data, &["localhost", "5432", "app", "admin"]),
+            Some("pass:with:colon".to_string())
+        );
+        assert_eq!(
+            find_password(data, &["localhost", "5432", "app", "nobody"]),
+            None
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{ApplyStatus, ChangeSet, MigrationChangeSets, MigrationError, SqlComparison};
+
+/**
+ * A change of history to reconcile it with local change sets.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::large_enum_variant)]
+pub enum RepairAction {
+    /**
+     * Replace name, SQL and checksum of an applied change set with local
+     * ones.
+     */
+    Update {
+        /**
+         * Change set recorded in history
+         */
+        stored: ChangeSet,
+
+        /**
+         * Change set in local files
+         */
+        local: ChangeSet,
+    },
+
+    /**
+     * Remove an applied change set which is not found in local files.
+     */
+    Remove(ChangeSet),
+
+    /**
+     * Mark a change set left in progress or failed as applied, after the
+     * database is fixed by hand.
+     */
+    Resolve(ChangeSet),
+}
+
+impl RepairAction {
+    /**
+     * Change set recorded in history
+     */
+    pub fn stored(&self) -> &ChangeSet {
+        match self {
+            RepairAction::Update { stored, .. } => stored,
+            RepairAction::Remove(stored) => stored,
+            RepairAction::Resolve(stored) => stored,
+        }
+    }
+}
+
+/**
+ * Plan repair of `applied_sets` with `local_sets`.
+ *
+ * Only change sets of `versions` are repaired if they are given.
+ */
+pub(crate) fn plan_repair(
+    local_sets: &MigrationChangeSets,
+    applied_sets: &MigrationChangeSets,
+    versions: Option<&[i32]>,
+    comparison: SqlComparison,
+) -> Vec<RepairAction> {
+    let mut actions = Vec::new();
+    for stored in applied_sets.change_sets.iter().filter(|stored| {
+        versions
+            .map(|x| x.contains(&stored.name.version))
+            .unwrap_or(true)
+    }) {
+        let local = local_sets
+            .change_sets
+            .iter()
+            .find(|local| local.name.version == stored.name.version);
+        match local {
+            Some(local)
+                if local.name == stored.name
+                    && comparison.same_sql(&local.up_sql, &stored.up_sql)
+                    && comparison.same_optional_sql(
+                        local.down_sql.as_deref(),
+                        stored.down_sql.as_deref(),
+                    ) => {}
+            Some(local) => actions.push(RepairAction::Update {
+                stored: stored.clone(),
+                local: local.clone(),
+            }),
+            None => {
+                actions.push(RepairAction::Remove(stored.clone()));
+                continue;
+            }
+        }
+        if stored
+            .applied
+            .as_ref()
+            .map(|x| x.status != ApplyStatus::Applied)
+            .unwrap_or(false)
+        {
+            actions.push(RepairAction::Resolve(stored.clone()));
+        }
+    }
+    actions
+}
+
+/**
+ * Fail with `RepairMismatchError` unless `actions` are the `expected` ones.
+ */
+pub(crate) fn check_expected_repair(
+    group_name: &str,
+    expected: Option<&[RepairAction]>,
+    actions: &[RepairAction],
+) -> Result<(), MigrationError> {
+    match expected {
+        Some(expected) if expected != actions => Err(MigrationError::RepairMismatchError {
+            group_name: group_name.to_string(),
+            expected: repaired_versions(expected),
+            found: repaired_versions(actions),
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn repaired_versions(actions: &[RepairAction]) -> Vec<i32> {
+    actions.iter().map(|x| x.stored().name.version).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppliedInfo;
+
+    #[test]
+    fn test_plan_repair() {
+        let local_sets = MigrationChangeSets::load_dir("generic", "./schema").unwrap();
+        let mut applied_sets = local_sets.subset(..3);
+        applied_sets.change_sets[0].up_sql += "\n-- typo";
+        applied_sets.change_sets[2].name.version = 12;
+
+        assert_eq!(
+            plan_repair(&local_sets, &applied_sets, None, SqlComparison::Exact),
+            vec![
+                RepairAction::Update {
+                    stored: applied_sets.change_sets[0].clone(),
+                    local: local_sets.change_sets[0].clone(),
+                },
+                RepairAction::Remove(applied_sets.change_sets[2].clone()),
+            ]
+        );
+        assert_eq!(
+            plan_repair(
+                &local_sets,
+                &applied_sets,
+                Some(&[12]),
+                SqlComparison::Exact
+            ),
+            vec![RepairAction::Remove(applied_sets.change_sets[2].clone())]
+        );
+        assert!(plan_repair(&local_sets, &local_sets, None, SqlComparison::Exact).is_empty());
+        // only a comment is added
+        assert_eq!(
+            plan_repair(&local_sets, &applied_sets, None, SqlComparison::Normalized),
+            vec![RepairAction::Remove(applied_sets.change_sets[2].clone())]
+        );
+
+        let mut applied_sets = local_sets.subset(..1);
+        applied_sets.change_sets[0].applied = Some(AppliedInfo {
+            status: ApplyStatus::Failed,
+            ..AppliedInfo::default()
+        });
+        assert_eq!(
+            plan_repair(&local_sets, &applied_sets, None, SqlComparison::Exact),
+            vec![RepairAction::Resolve(applied_sets.change_sets[0].clone())]
+        );
+    }
+}
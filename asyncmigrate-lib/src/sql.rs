@@ -0,0 +1,363 @@
+use crate::SqlLocation;
+
+/**
+ * A statement in an SQL script
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Statement<'a> {
+    /**
+     * Byte offset of the statement in the script
+     */
+    pub offset: usize,
+
+    /**
+     * SQL of the statement without the trailing semicolon
+     */
+    pub sql: &'a str,
+}
+
+/**
+ * Split an SQL script into statements by semicolons.
+ *
+ * Semicolons in quoted strings and identifiers, dollar-quoted strings and
+ * comments are ignored. Statements which only have comments are skipped.
+ */
+pub(crate) fn split_statements(script: &str) -> Vec<Statement<'_>> {
+    let bytes = script.as_bytes();
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut has_code = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = find_from(script, i, "\n")
+                    .map(|x| x + 1)
+                    .unwrap_or(bytes.len());
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = skip_block_comment(bytes, i);
+                continue;
+            }
+            b'\'' => {
+                let escape = i > 0 && (bytes[i - 1] == b'E' || bytes[i - 1] == b'e');
+                i = skip_quoted(bytes, i, b'\'', escape);
+            }
+            b'"' => {
+                i = skip_quoted(bytes, i, b'"', false);
+            }
+            b'$' => {
+                if let Some(tag_end) = dollar_tag_end(bytes, i) {
+                    let tag = &script[i..tag_end];
+                    i = find_from(script, tag_end, tag)
+                        .map(|x| x + tag.len())
+                        .unwrap_or(bytes.len());
+                } else {
+                    i += 1;
+                }
+            }
+            b';' => {
+                if has_code {
+                    statements.push(trimmed_statement(script, start, i));
+                }
+                i += 1;
+                start = i;
+                has_code = false;
+                continue;
+            }
+            _ => {
+                i += 1;
+            }
+        }
+        if !bytes[i - 1].is_ascii_whitespace() {
+            has_code = true;
+        }
+    }
+    if has_code {
+        statements.push(trimmed_statement(script, start, bytes.len()));
+    }
+    statements
+}
+
+/**
+ * Index of the statement which contains byte `offset`. An offset between
+ * statements belongs to the next one.
+ */
+pub(crate) fn statement_at(statements: &[Statement<'_>], offset: usize) -> Option<usize> {
+    statements
+        .iter()
+        .position(|x| offset < x.offset + x.sql.len())
+}
+
+/**
+ * Byte offset of the character at `position`, counted from 1 like the
+ * `position` field of Postgres errors
+ */
+pub(crate) fn char_offset(script: &str, position: usize) -> Option<usize> {
+    script
+        .char_indices()
+        .nth(position.checked_sub(1)?)
+        .map(|x| x.0)
+}
+
+/**
+ * Line and column of byte `offset` in a script
+ */
+pub(crate) fn locate(script: &str, offset: usize) -> SqlLocation {
+    let line_start = script[..offset].rfind('\n').map(|x| x + 1).unwrap_or(0);
+    let line_end = script[offset..]
+        .find('\n')
+        .map(|x| x + offset)
+        .unwrap_or(script.len());
+    SqlLocation {
+        line: script[..offset].matches('\n').count() + 1,
+        column: script[line_start..offset].chars().count() + 1,
+        line_text: script[line_start..line_end]
+            .trim_end_matches('\r')
+            .to_string(),
+    }
+}
+
+/**
+ * Split an SQL script into tokens. Comments and whitespace between tokens
+ * are dropped. Quoted strings and identifiers and dollar-quoted strings are
+ * single tokens kept as written.
+ */
+pub(crate) fn tokens(script: &str) -> Vec<&str> {
+    let bytes = script.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            x if x.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = find_from(script, i, "\n").unwrap_or(bytes.len());
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = skip_block_comment(bytes, i);
+                continue;
+            }
+            b'\'' => {
+                i = skip_quoted(bytes, i, b'\'', false);
+            }
+            b'"' => {
+                i = skip_quoted(bytes, i, b'"', false);
+            }
+            b'$' if dollar_tag_end(bytes, i).is_some() => {
+                let tag_end = dollar_tag_end(bytes, i).unwrap();
+                let tag = &script[i..tag_end];
+                i = find_from(script, tag_end, tag)
+                    .map(|x| x + tag.len())
+                    .unwrap_or(bytes.len());
+            }
+            x if is_word_byte(x) => {
+                while i < bytes.len() && is_word_byte(bytes[i]) {
+                    i += 1;
+                }
+                if bytes.get(i) == Some(&b'\'') && matches!(&script[start..i], "E" | "e") {
+                    i = skip_quoted(bytes, i, b'\'', true);
+                }
+            }
+            x if is_operator_byte(x) => {
+                i += 1;
+                while i < bytes.len()
+                    && is_operator_byte(bytes[i])
+                    && !matches!(&bytes[i..(i + 2).min(bytes.len())], b"--" | b"/*")
+                {
+                    i += 1;
+                }
+            }
+            _ => {
+                i += 1;
+            }
+        }
+        tokens.push(&script[start..i]);
+    }
+    tokens
+}
+
+fn is_word_byte(x: u8) -> bool {
+    x.is_ascii_alphanumeric() || x == b'_' || x == b'$' || !x.is_ascii()
+}
+
+fn is_operator_byte(x: u8) -> bool {
+    b"+-*/<>=~!@#%^&|`?".contains(&x)
+}
+
+fn trimmed_statement(script: &str, start: usize, end: usize) -> Statement<'_> {
+    let sql = &script[start..end];
+    let trimmed = sql.trim_start();
+    Statement {
+        offset: start + sql.len() - trimmed.len(),
+        sql: trimmed.trim_end(),
+    }
+}
+
+fn find_from(script: &str, from: usize, pattern: &str) -> Option<usize> {
+    script[from..].find(pattern).map(|x| x + from)
+}
+
+/**
+ * Position after a block comment, which can be nested
+ */
+fn skip_block_comment(bytes: &[u8], mut i: usize) -> usize {
+    let mut depth = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'/' && bytes.get(i + 1) == Some(&b'*') {
+            depth += 1;
+            i += 2;
+        } else if bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/') {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    i
+}
+
+/**
+ * Position after a quoted string or identifier. A doubled quote is an
+ * escaped quote. In `E'...'` strings, a backslash escapes the next
+ * character.
+ */
+fn skip_quoted(bytes: &[u8], mut i: usize, quote: u8, backslash_escape: bool) -> usize {
+    i += 1;
+    while i < bytes.len() {
+        if backslash_escape && bytes[i] == b'\\' {
+            i += 2;
+        } else if bytes[i] == quote {
+            if bytes.get(i + 1) == Some(&quote) {
+                i += 2;
+            } else {
+                return i + 1;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    i
+}
+
+/**
+ * End of a dollar quote tag such as `$$` or `$body$` starting at `i`
+ */
+fn dollar_tag_end(bytes: &[u8], i: usize) -> Option<usize> {
+    // `$1` is a parameter, and `a$b` is a part of an identifier
+    if i > 0 && (bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'_') {
+        return None;
+    }
+    let mut j = i + 1;
+    while j < bytes.len() && (bytes[j].is_ascii_alphanumeric() || bytes[j] == b'_') {
+        if j == i + 1 && bytes[j].is_ascii_digit() {
+            return None;
+        }
+        j += 1;
+    }
+    if bytes.get(j) == Some(&b'$') {
+        Some(j + 1)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_statements() {
+        let script = "-- create; table\n\
+                      CREATE TABLE a(x TEXT DEFAULT 'a;''b');\n\
+                      /* comment; /* nested; */ */\n\
+                      CREATE FUNCTION f() RETURNS TEXT AS $body$ SELECT ';' $body$ LANGUAGE sql;\n\
+                      SELECT E'\\';', \"x;\"\n\
+                      ; -- only comment;\n";
+        let statements: Vec<_> = split_statements(script).iter().map(|x| x.sql).collect();
+        assert_eq!(
+            statements,
+            vec![
+                "-- create; table\nCREATE TABLE a(x TEXT DEFAULT 'a;''b')",
+                "/* comment; /* nested; */ */\nCREATE FUNCTION f() RETURNS TEXT AS $body$ SELECT ';' $body$ LANGUAGE sql",
+                "SELECT E'\\';', \"x;\"",
+            ]
+        );
+        assert_eq!(split_statements(script)[1].offset, 57);
+        assert_eq!(split_statements("SELECT $1; SELECT 2").len(), 2);
+        assert!(split_statements("-- nothing to do\n").is_empty());
+    }
+
+    #[test]
+    fn test_locate() {
+        let script = "CREATE TABLE a(x TEXT);\n-- é\nCREATE TABL b;\n";
+        let statements = split_statements(script);
+        let offset = char_offset(script, 37).unwrap();
+        assert_eq!(&script[offset..offset + 4], "TABL");
+        assert_eq!(statement_at(&statements, offset), Some(1));
+        assert_eq!(statement_at(&statements, 23), Some(1));
+        assert_eq!(statement_at(&statements, 5), Some(0));
+        assert_eq!(
+            locate(script, offset),
+            SqlLocation {
+                line: 3,
+                column: 8,
+                line_text: "CREATE TABL b;".to_string(),
+            }
+        );
+        assert_eq!(char_offset(script, 0), None);
+        assert_eq!(char_offset(script, 100), None);
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(
+            tokens(
+                "-- comment\r\n\
+                 CREATE TABLE a(x TEXT DEFAULT 'a  ''b', y INTEGER CHECK(y>=-1)) ;  \r\n\
+                 /* block /* nested */ */ SELECT E'\\'', \"Some  Name\", $b$ x  y $b$, $1--tail"
+            ),
+            vec![
+                "CREATE",
+                "TABLE",
+                "a",
+                "(",
+                "x",
+                "TEXT",
+                "DEFAULT",
+                "'a  ''b'",
+                ",",
+                "y",
+                "INTEGER",
+                "CHECK",
+                "(",
+                "y",
+                ">=-",
+                "1",
+                ")",
+                ")",
+                ";",
+                "SELECT",
+                "E'\\''",
+                ",",
+                "\"Some  Name\"",
+                ",",
+                "$b$ x  y $b$",
+                ",",
+                "$1",
+            ]
+        );
+        assert_eq!(tokens("SELECT 1 -- x\n+2"), vec!["SELECT", "1", "+", "2"]);
+        assert_eq!(tokens("SELECT 1+/* x */2"), vec!["SELECT", "1", "+", "2"]);
+    }
+}
@@ -0,0 +1,33 @@
+//! `tracing` events which compile to nothing without the `tracing` feature
+
+/**
+ * Emit a debug event, such as SQL being run.
+ */
+macro_rules! trace_debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
+/**
+ * Emit an error event.
+ */
+macro_rules! trace_error {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::error!($($arg)*);
+    };
+}
+
+/**
+ * SQLSTATE of a Postgres error, recorded as `code` of error events
+ */
+#[cfg(feature = "tracing")]
+pub(crate) fn sql_state(error: &crate::MigrationError) -> Option<&str> {
+    match error {
+        crate::MigrationError::PostgresError(e)
+        | crate::MigrationError::StatementError { source: e, .. } => e.code().map(|x| x.code()),
+        _ => None,
+    }
+}